@@ -1,10 +1,11 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rust_compressor::Algorithm;
 
 fn bench_rle_compression(c: &mut Criterion) {
     let input = b"AAABBBCCCCCDDDDE".repeat(100);
     c.bench_function("rle_compress", |b| {
         b.iter(|| {
-            let result = rust_compressor::compress(black_box(&input), true);
+            let result = rust_compressor::compress(black_box(&input), Algorithm::Rle);
             assert!(result.is_ok());
             black_box(result.unwrap());
         })
@@ -13,10 +14,10 @@ fn bench_rle_compression(c: &mut Criterion) {
 
 fn bench_rle_decompression(c: &mut Criterion) {
     let input = b"AAABBBCCCCCDDDDE".repeat(100);
-    let compressed = rust_compressor::compress(&input, true).unwrap();
+    let compressed = rust_compressor::compress(&input, Algorithm::Rle).unwrap();
     c.bench_function("rle_decompress", |b| {
         b.iter(|| {
-            let result = rust_compressor::decompress(black_box(&compressed), true);
+            let result = rust_compressor::decompress(black_box(&compressed));
             assert!(result.is_ok());
             black_box(result.unwrap());
         })
@@ -27,7 +28,19 @@ fn bench_lz77_compression(c: &mut Criterion) {
     let input = b"ABABABABABAB".repeat(100);
     c.bench_function("lz77_compress", |b| {
         b.iter(|| {
-            let result = rust_compressor::compress(black_box(&input), false);
+            let result = rust_compressor::compress(black_box(&input), Algorithm::Lz77);
+            assert!(result.is_ok());
+            black_box(result.unwrap());
+        })
+    });
+}
+
+fn bench_lz77_decompression(c: &mut Criterion) {
+    let input = b"ABABABABABAB".repeat(100);
+    let compressed = rust_compressor::compress(&input, Algorithm::Lz77).unwrap();
+    c.bench_function("lz77_decompress", |b| {
+        b.iter(|| {
+            let result = rust_compressor::decompress(black_box(&compressed));
             assert!(result.is_ok());
             black_box(result.unwrap());
         })
@@ -39,6 +52,6 @@ criterion_group!(
     bench_rle_compression,
     bench_rle_decompression,
     bench_lz77_compression,
-    //bench_lz77_decompression
+    bench_lz77_decompression
 );
 criterion_main!(benches);
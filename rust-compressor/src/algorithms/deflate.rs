@@ -0,0 +1,116 @@
+// DEFLATE (RFC 1951) and its zlib (RFC 1950) and gzip (RFC 1952) wrappers,
+// backed by flate2.
+//
+// Raw DEFLATE has no header of its own and only travels inside this crate's
+// container. Zlib and gzip streams are self-describing, so they are written
+// bare and can be read by standard tools such as `gunzip`.
+
+use std::io::{Read, Write};
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Our levels 1-9 mean the same as zlib's.
+pub fn compression(level: u32) -> Compression {
+    Compression::new(level)
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.len() >= 2 && data[..2] == GZIP_MAGIC
+}
+
+// A zlib header is two bytes: method 8 (deflate) with a window of at most
+// 32K, and a check value making the pair a multiple of 31.
+pub fn is_zlib(data: &[u8]) -> bool {
+    data.len() >= 2
+        && data[0] & 0x0f == 8
+        && data[0] >> 4 <= 7
+        && (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 == 0
+}
+
+pub fn compress_deflate(data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), compression(level));
+    encoder.write_all(data).map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+pub fn decompress_deflate(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    read_all(DeflateDecoder::new(data), limit)
+}
+
+pub fn compress_zlib(data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), compression(level));
+    encoder.write_all(data).map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+pub fn decompress_zlib(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    read_all(ZlibDecoder::new(data), limit)
+}
+
+pub fn compress_gzip(data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), compression(level));
+    encoder.write_all(data).map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+// Like `gunzip`, reads every member of a multi-member file.
+pub fn decompress_gzip(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    read_all(MultiGzDecoder::new(data), limit)
+}
+
+// Stops one byte past `limit`, so a small stream that inflates to far more
+// than expected fails before it is all in memory.
+fn read_all<R: Read>(decoder: R, limit: usize) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let cap = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+    decoder.take(cap).read_to_end(&mut output).map_err(|e| e.to_string())?;
+    if output.len() > limit {
+        return Err(format!("Output exceeds the expected {} bytes", limit));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        let input = b"deflate me, deflate me again, deflate me once more".repeat(20);
+        for level in [1, 6, 9] {
+            let raw = compress_deflate(&input, level).unwrap();
+            assert!(raw.len() < input.len() / 4);
+            assert_eq!(decompress_deflate(&raw, usize::MAX).unwrap(), input);
+            assert_eq!(decompress_zlib(&compress_zlib(&input, level).unwrap(), usize::MAX).unwrap(), input);
+            assert_eq!(decompress_gzip(&compress_gzip(&input, level).unwrap(), usize::MAX).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_sniffing() {
+        let zlib = compress_zlib(b"abc", 6).unwrap();
+        let gzip = compress_gzip(b"abc", 6).unwrap();
+        assert!(is_zlib(&zlib) && !is_gzip(&zlib));
+        assert!(is_gzip(&gzip) && !is_zlib(&gzip));
+        assert!(!is_zlib(b"RZIP") && !is_gzip(b"RZIP"));
+    }
+
+    #[test]
+    fn test_multi_member_gzip() {
+        let mut data = compress_gzip(b"first ", 6).unwrap();
+        data.extend(compress_gzip(b"second", 6).unwrap());
+        assert_eq!(decompress_gzip(&data, usize::MAX).unwrap(), b"first second");
+    }
+
+    #[test]
+    fn test_corrupt_gzip_rejected() {
+        let mut data = compress_gzip(b"some text to protect", 6).unwrap();
+        let crc = data.len() - 8;
+        data[crc] ^= 0xff;
+        assert!(decompress_gzip(&data, usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+// Hash-chain match finder for the LZ77 encoders.
+//
+// Every position is hashed on its next three bytes. `head` maps a hash to the
+// most recent position with that hash and `prev` links each position to the
+// previous one in the same bucket, so a lookup only visits earlier positions
+// that share a 3-byte prefix instead of scanning the whole window.
+
+// The hash table grows with the window so that large windows don't fill each
+// bucket with unrelated positions.
+const MIN_HASH_BITS: u32 = 15;
+const MAX_HASH_BITS: u32 = 20;
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchFinderConfig {
+    pub window_size: usize,
+    pub chain_depth: usize,
+    pub min_match: usize,
+    pub max_match: usize,
+}
+
+impl Default for MatchFinderConfig {
+    fn default() -> Self {
+        MatchFinderConfig {
+            window_size: 32 * 1024,
+            chain_depth: 32,
+            min_match: 3,
+            max_match: 258,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub offset: usize,
+    pub length: usize,
+}
+
+pub struct MatchFinder<'a> {
+    data: &'a [u8],
+    config: MatchFinderConfig,
+    hash_bits: u32,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl<'a> MatchFinder<'a> {
+    pub fn new(data: &'a [u8], config: MatchFinderConfig) -> Self {
+        assert!(config.window_size > 0, "window size must be non-zero");
+        assert!(config.min_match >= 3, "minimum match length must be at least 3");
+        let span = config.window_size.min(data.len()).max(1);
+        let hash_bits = (usize::BITS - span.leading_zeros()).clamp(MIN_HASH_BITS, MAX_HASH_BITS);
+        MatchFinder {
+            data,
+            config,
+            hash_bits,
+            head: vec![NIL; 1 << hash_bits],
+            prev: vec![NIL; config.window_size.min(data.len()).max(1)],
+        }
+    }
+
+    pub fn config(&self) -> &MatchFinderConfig {
+        &self.config
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = self.data;
+        let key = (d[pos] as u32) << 16 | (d[pos + 1] as u32) << 8 | d[pos + 2] as u32;
+        (key.wrapping_mul(2654435761) >> (32 - self.hash_bits)) as usize
+    }
+
+    // Records `pos` so later lookups can match against it. Positions must be
+    // inserted in increasing order.
+    pub fn insert(&mut self, pos: usize) {
+        if pos + 3 > self.data.len() {
+            return;
+        }
+        let h = self.hash(pos);
+        let slot = pos % self.prev.len();
+        self.prev[slot] = self.head[h];
+        self.head[h] = pos;
+    }
+
+    // Returns the longest match for the bytes at `pos` against already
+    // inserted positions, if it reaches the configured minimum length.
+    pub fn find(&self, pos: usize) -> Option<Match> {
+        let mut best: Option<Match> = None;
+        self.walk_chain(pos, |m| {
+            if best.is_none_or(|b| m.length > b.length) {
+                best = Some(m);
+            }
+        });
+        best
+    }
+
+    // Returns every match that is longer than all closer ones, ordered by
+    // increasing length (and therefore increasing offset). Used by parsers
+    // that weigh the cost of several candidates.
+    pub fn find_all(&self, pos: usize) -> Vec<Match> {
+        let mut matches = Vec::new();
+        self.walk_chain(pos, |m| matches.push(m));
+        matches
+    }
+
+    fn walk_chain<F: FnMut(Match)>(&self, pos: usize, mut visit: F) {
+        let data = self.data;
+        if pos + self.config.min_match > data.len() {
+            return;
+        }
+        let max_len = self.config.max_match.min(data.len() - pos);
+
+        let mut candidate = self.head[self.hash(pos)];
+        let mut depth = 0;
+        let mut best_len = 0;
+
+        while candidate != NIL && candidate < pos && depth < self.config.chain_depth {
+            let offset = pos - candidate;
+            if offset > self.config.window_size || offset > self.prev.len() {
+                break;
+            }
+
+            // Cheap rejection: a longer match has to agree at the current
+            // best length first.
+            if data[candidate + best_len.min(max_len - 1)] == data[pos + best_len.min(max_len - 1)] {
+                let mut length = 0;
+                while length < max_len && data[candidate + length] == data[pos + length] {
+                    length += 1;
+                }
+                if length >= self.config.min_match && length > best_len {
+                    best_len = length;
+                    visit(Match { offset, length });
+                    if length == max_len {
+                        break;
+                    }
+                }
+            }
+
+            let next = self.prev[candidate % self.prev.len()];
+            if next == NIL || next >= candidate {
+                break;
+            }
+            candidate = next;
+            depth += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_range(finder: &mut MatchFinder, end: usize) {
+        for pos in 0..end {
+            finder.insert(pos);
+        }
+    }
+
+    #[test]
+    fn test_finds_repeated_phrase() {
+        let data = b"the quick brown fox, the quick brown dog";
+        let mut finder = MatchFinder::new(data, MatchFinderConfig::default());
+        insert_range(&mut finder, 21);
+        let m = finder.find(21).unwrap();
+        assert_eq!(m.offset, 21);
+        assert_eq!(m.length, 16);
+    }
+
+    #[test]
+    fn test_no_match_in_unique_data() {
+        let data = b"ABCDEFGHIJKLMNOP";
+        let mut finder = MatchFinder::new(data, MatchFinderConfig::default());
+        insert_range(&mut finder, 8);
+        assert_eq!(finder.find(8), None);
+    }
+
+    #[test]
+    fn test_overlapping_run() {
+        let data = [7u8; 100];
+        let mut finder = MatchFinder::new(&data, MatchFinderConfig::default());
+        finder.insert(0);
+        let m = finder.find(1).unwrap();
+        assert_eq!(m.offset, 1);
+        assert_eq!(m.length, 99);
+    }
+
+    #[test]
+    fn test_large_window() {
+        let mut data: Vec<u8> = (0..40_000u32).map(|i| (i.wrapping_mul(7919) >> 3) as u8).collect();
+        let phrase = b"a phrase that only occurs twice";
+        data[100..100 + phrase.len()].copy_from_slice(phrase);
+        data.extend_from_slice(phrase);
+
+        let pos = data.len() - phrase.len();
+        let config = MatchFinderConfig {
+            window_size: 64 * 1024,
+            chain_depth: 4096,
+            ..MatchFinderConfig::default()
+        };
+        let mut finder = MatchFinder::new(&data, config);
+        insert_range(&mut finder, pos);
+        let m = finder.find(pos).unwrap();
+        assert_eq!(m.offset, pos - 100);
+        assert!(m.length >= phrase.len());
+
+        let small = MatchFinderConfig {
+            window_size: 32 * 1024,
+            ..config
+        };
+        let mut finder = MatchFinder::new(&data, small);
+        insert_range(&mut finder, pos);
+        assert!(finder.find(pos).is_none_or(|m| m.offset <= 32 * 1024));
+    }
+
+    #[test]
+    fn test_find_all_orders_by_length() {
+        let data = b"abcdXabcdeYabcdefZabcdef";
+        let mut finder = MatchFinder::new(data, MatchFinderConfig::default());
+        insert_range(&mut finder, 18);
+        let all = finder.find_all(18);
+        assert_eq!(all.last().copied(), finder.find(18));
+        assert!(all.windows(2).all(|w| w[0].length < w[1].length && w[0].offset < w[1].offset));
+        assert_eq!(all[0], Match { offset: 7, length: 6 });
+    }
+
+    #[test]
+    fn test_respects_max_match() {
+        let data = [1u8; 1000];
+        let config = MatchFinderConfig {
+            max_match: 255,
+            ..MatchFinderConfig::default()
+        };
+        let mut finder = MatchFinder::new(&data, config);
+        finder.insert(0);
+        assert_eq!(finder.find(1).unwrap().length, 255);
+    }
+}
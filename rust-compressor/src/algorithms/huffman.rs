@@ -0,0 +1,444 @@
+// Canonical, length-limited Huffman coding plus the bit I/O it needs.
+//
+// Bits are packed least significant first and codes are written most
+// significant bit first, the same convention DEFLATE uses. Code tables are
+// transmitted as code lengths only; both sides rebuild identical canonical
+// codes from them.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+pub const MAX_CODE_LENGTH: u8 = 15;
+
+// Code-length alphabet used to serialize tables: 0-15 are literal lengths,
+// 16 repeats the previous length 3-6 times, 17 and 18 emit runs of 3-10 and
+// 11-138 zeros.
+const CLEN_ALPHABET: usize = 19;
+const CLEN_MAX_LENGTH: u8 = 7;
+const CLEN_ORDER: [usize; CLEN_ALPHABET] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+pub struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    bits: u32,
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter {
+            output: Vec::new(),
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        debug_assert!(count <= 32);
+        self.buffer |= (value as u64) << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+}
+
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u64,
+    bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, String> {
+        debug_assert!(count <= 32);
+        while self.bits < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| "Unexpected end of Huffman data".to_string())?;
+            self.buffer |= (byte as u64) << self.bits;
+            self.pos += 1;
+            self.bits += 8;
+        }
+        let value = (self.buffer & ((1u64 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.bits -= count;
+        Ok(value)
+    }
+
+    pub fn read_bit(&mut self) -> Result<u32, String> {
+        self.read_bits(1)
+    }
+}
+
+// Computes Huffman code lengths for `freqs`, none longer than `max_length`.
+// Symbols with zero frequency get length 0. When the optimal tree is too
+// deep, frequencies are flattened and the tree rebuilt until it fits.
+pub fn build_code_lengths(freqs: &[u32], max_length: u8) -> Vec<u8> {
+    let mut weights: Vec<u64> = freqs.iter().map(|&f| f as u64).collect();
+    loop {
+        let lengths = huffman_lengths(&weights);
+        if lengths.iter().all(|&l| l <= max_length as u32) {
+            return lengths.into_iter().map(|l| l as u8).collect();
+        }
+        for w in weights.iter_mut().filter(|w| **w > 0) {
+            *w = (*w >> 1) | 1;
+        }
+    }
+}
+
+fn huffman_lengths(weights: &[u64]) -> Vec<u32> {
+    let mut lengths = vec![0u32; weights.len()];
+    let used: Vec<usize> = (0..weights.len()).filter(|&i| weights[i] > 0).collect();
+
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // Leaves are nodes 0..used.len(); internal nodes are appended after.
+    let mut parent = vec![usize::MAX; used.len()];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = used
+        .iter()
+        .enumerate()
+        .map(|(node, &sym)| Reverse((weights[sym], node)))
+        .collect();
+
+    while heap.len() > 1 {
+        let Reverse((w1, a)) = heap.pop().unwrap();
+        let Reverse((w2, b)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[a] = node;
+        parent[b] = node;
+        heap.push(Reverse((w1 + w2, node)));
+    }
+
+    // Parents always have higher indices, so depths resolve in one pass
+    // from the root down.
+    let mut depth = vec![0u32; parent.len()];
+    for node in (0..parent.len() - 1).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+    for (leaf, &sym) in used.iter().enumerate() {
+        lengths[sym] = depth[leaf];
+    }
+    lengths
+}
+
+// Assigns canonical codes for `lengths`, returned bit-reversed so they can
+// be passed straight to `BitWriter::write_bits`.
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut bl_count = [0u32; MAX_CODE_LENGTH as usize + 1];
+    for &len in lengths {
+        bl_count[len as usize] += 1;
+    }
+    bl_count[0] = 0;
+
+    let mut next_code = [0u32; MAX_CODE_LENGTH as usize + 1];
+    let mut code = 0;
+    for bits in 1..=MAX_CODE_LENGTH as usize {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            code.reverse_bits() >> (32 - len as u32)
+        })
+        .collect()
+}
+
+pub struct Encoder {
+    codes: Vec<u32>,
+    lengths: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new(lengths: &[u8]) -> Self {
+        Encoder {
+            codes: canonical_codes(lengths),
+            lengths: lengths.to_vec(),
+        }
+    }
+
+    pub fn write(&self, writer: &mut BitWriter, symbol: usize) {
+        debug_assert!(self.lengths[symbol] > 0, "symbol {} has no code", symbol);
+        writer.write_bits(self.codes[symbol], self.lengths[symbol] as u32);
+    }
+}
+
+pub struct Decoder {
+    counts: [u16; MAX_CODE_LENGTH as usize + 1],
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    pub fn new(lengths: &[u8]) -> Result<Self, String> {
+        let mut counts = [0u16; MAX_CODE_LENGTH as usize + 1];
+        for &len in lengths {
+            if len > MAX_CODE_LENGTH {
+                return Err(format!("Huffman code length {} exceeds {}", len, MAX_CODE_LENGTH));
+            }
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Reject over-subscribed tables; incomplete ones are allowed so a
+        // single-symbol alphabet can use a one-bit code.
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err("Over-subscribed Huffman table".to_string());
+            }
+        }
+
+        let mut offsets = [0usize; MAX_CODE_LENGTH as usize + 2];
+        for len in 1..=MAX_CODE_LENGTH as usize {
+            offsets[len + 1] = offsets[len] + counts[len] as usize;
+        }
+        let mut symbols = vec![0u16; offsets[MAX_CODE_LENGTH as usize + 1]];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[offsets[len as usize]] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Decoder { counts, symbols })
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> Result<usize, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_CODE_LENGTH as usize {
+            code |= reader.read_bit()? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code".to_string())
+    }
+}
+
+// Serializes a table of code lengths with the code-length alphabet, which is
+// itself Huffman coded. The receiver must know `lengths.len()`.
+pub fn write_code_lengths(writer: &mut BitWriter, lengths: &[u8]) {
+    let ops = run_length_ops(lengths);
+
+    let mut freqs = [0u32; CLEN_ALPHABET];
+    for &(symbol, _) in &ops {
+        freqs[symbol as usize] += 1;
+    }
+    let clen_lengths = build_code_lengths(&freqs, CLEN_MAX_LENGTH);
+
+    let mut count = CLEN_ALPHABET;
+    while count > 4 && clen_lengths[CLEN_ORDER[count - 1]] == 0 {
+        count -= 1;
+    }
+    writer.write_bits((count - 4) as u32, 4);
+    for &symbol in &CLEN_ORDER[..count] {
+        writer.write_bits(clen_lengths[symbol] as u32, 3);
+    }
+
+    let encoder = Encoder::new(&clen_lengths);
+    for (symbol, extra) in ops {
+        encoder.write(writer, symbol as usize);
+        match symbol {
+            16 => writer.write_bits(extra - 3, 2),
+            17 => writer.write_bits(extra - 3, 3),
+            18 => writer.write_bits(extra - 11, 7),
+            _ => {}
+        }
+    }
+}
+
+pub fn read_code_lengths(reader: &mut BitReader, total: usize) -> Result<Vec<u8>, String> {
+    let count = reader.read_bits(4)? as usize + 4;
+    let mut clen_lengths = [0u8; CLEN_ALPHABET];
+    for &symbol in &CLEN_ORDER[..count] {
+        clen_lengths[symbol] = reader.read_bits(3)? as u8;
+    }
+    let decoder = Decoder::new(&clen_lengths)?;
+
+    let mut lengths = Vec::with_capacity(total);
+    while lengths.len() < total {
+        let (value, repeat) = match decoder.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| "Repeat code with no previous length".to_string())?;
+                (previous, reader.read_bits(2)? as usize + 3)
+            }
+            17 => (0, reader.read_bits(3)? as usize + 3),
+            _ => (0, reader.read_bits(7)? as usize + 11),
+        };
+        if lengths.len() + repeat > total {
+            return Err("Code length run exceeds table size".to_string());
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+    Ok(lengths)
+}
+
+// Splits `lengths` into code-length symbols with their repeat counts.
+fn run_length_ops(lengths: &[u8]) -> Vec<(u8, u32)> {
+    let mut ops = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == value {
+            run += 1;
+        }
+        i += run;
+
+        if value == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                ops.push((18, n as u32));
+                run -= n;
+            }
+            if run >= 3 {
+                ops.push((17, run as u32));
+                run = 0;
+            }
+        } else {
+            ops.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                ops.push((16, n as u32));
+                run -= n;
+            }
+        }
+        for _ in 0..run {
+            ops.push((value, 0));
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bit_round_trip() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0xabcd, 16);
+        writer.write_bits(1, 1);
+        let bytes = writer.finish();
+        assert_eq!(bytes.len(), 3);
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.read_bits(16).unwrap(), 0xabcd);
+        assert_eq!(reader.read_bit().unwrap(), 1);
+        assert!(reader.read_bits(8).is_err());
+    }
+
+    #[test]
+    fn test_lengths_are_limited() {
+        // Fibonacci weights produce a maximally skewed tree.
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            let n = freqs.len();
+            freqs.push(freqs[n - 1] + freqs[n - 2]);
+        }
+        let lengths = build_code_lengths(&freqs, MAX_CODE_LENGTH);
+        assert!(lengths.iter().all(|&l| l > 0 && l <= MAX_CODE_LENGTH));
+        let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+        assert!(kraft <= 1.0);
+    }
+
+    #[test]
+    fn test_encode_decode_symbols() {
+        let message = b"abracadabra, a canonical huffman test";
+        let mut freqs = vec![0u32; 256];
+        for &b in message {
+            freqs[b as usize] += 1;
+        }
+        let lengths = build_code_lengths(&freqs, MAX_CODE_LENGTH);
+        let encoder = Encoder::new(&lengths);
+
+        let mut writer = BitWriter::new();
+        write_code_lengths(&mut writer, &lengths);
+        for &b in message {
+            encoder.write(&mut writer, b as usize);
+        }
+        let bytes = writer.finish();
+        assert!(bytes.len() < message.len() + 40);
+
+        let mut reader = BitReader::new(&bytes);
+        let read_lengths = read_code_lengths(&mut reader, 256).unwrap();
+        assert_eq!(read_lengths, lengths);
+        let decoder = Decoder::new(&read_lengths).unwrap();
+        let decoded: Vec<u8> = (0..message.len())
+            .map(|_| decoder.decode(&mut reader).unwrap() as u8)
+            .collect();
+        assert_eq!(decoded, message.to_vec());
+    }
+
+    #[test]
+    fn test_single_symbol_alphabet() {
+        let mut freqs = vec![0u32; 10];
+        freqs[7] = 42;
+        let lengths = build_code_lengths(&freqs, MAX_CODE_LENGTH);
+        assert_eq!(lengths[7], 1);
+
+        let mut writer = BitWriter::new();
+        Encoder::new(&lengths).write(&mut writer, 7);
+        let bytes = writer.finish();
+        let decoder = Decoder::new(&lengths).unwrap();
+        assert_eq!(decoder.decode(&mut BitReader::new(&bytes)).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_rejects_oversubscribed_table() {
+        assert!(Decoder::new(&[1, 1, 1]).is_err());
+    }
+}
@@ -1,45 +1,371 @@
-const WINDOW_SIZE: usize = 20;
-const LOOKAHEAD_SIZE: usize = 15;
+use super::hash_chain::{MatchFinder, MatchFinderConfig};
 
-pub fn compress_lz77(data: &[u8]) -> Result<Vec<u8>, String> {
-    let mut output = Vec::new();
+// Token stream format used by `compress_lz77`:
+//
+// Tokens are written in groups of up to eight, each group preceded by a flag
+// byte whose bits (least significant first) mark the matching token as a
+// match (1) or a literal (0). A literal is the raw byte. A match is the
+// offset followed by `length - MIN_MATCH`, both as unsigned LEB128 varints,
+// so neither the window nor the match length is limited to a byte.
+pub const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 64 * 1024;
+
+// The legacy format stores offsets and lengths in a single byte each.
+const MAX_OFFSET: usize = 255;
+const MAX_LENGTH: usize = 255;
+const CHAIN_DEPTH: usize = 64;
+
+pub const MIN_LEVEL: u32 = 1;
+pub const MAX_LEVEL: u32 = 9;
+pub const DEFAULT_LEVEL: u32 = 6;
+
+pub(crate) fn check_level(level: u32) -> Result<(), String> {
+    if !(MIN_LEVEL..=MAX_LEVEL).contains(&level) {
+        return Err(format!(
+            "Compression level must be between {} and {}, got {}",
+            MIN_LEVEL, MAX_LEVEL, level
+        ));
+    }
+    Ok(())
+}
+
+// Matches at least this long are taken immediately by the optimal parser
+// instead of pricing every shorter alternative.
+const NICE_LENGTH: usize = 258;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { offset: usize, length: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parsing {
+    // Take the longest match at each position.
+    Greedy,
+    // Defer a match by one byte when the next position has a longer one.
+    Lazy,
+    // Choose the cheapest token sequence by encoded size.
+    Optimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lz77Params {
+    pub finder: MatchFinderConfig,
+    pub parsing: Parsing,
+}
+
+impl Lz77Params {
+    // Maps a compression level (1 = fastest, 9 = smallest) to search
+    // parameters. Out-of-range levels are clamped.
+    pub fn for_level(level: u32) -> Self {
+        let (window_size, chain_depth, min_match, parsing) = match level.clamp(MIN_LEVEL, MAX_LEVEL) {
+            1 => (16 * 1024, 4, 5, Parsing::Greedy),
+            2 => (16 * 1024, 8, 4, Parsing::Greedy),
+            3 => (32 * 1024, 16, 4, Parsing::Greedy),
+            4 => (32 * 1024, 16, 3, Parsing::Lazy),
+            5 => (64 * 1024, 32, 3, Parsing::Lazy),
+            6 => (64 * 1024, 64, 3, Parsing::Lazy),
+            7 => (256 * 1024, 128, 3, Parsing::Lazy),
+            8 => (1024 * 1024, 256, 3, Parsing::Optimal),
+            _ => (4 * 1024 * 1024, 1024, 3, Parsing::Optimal),
+        };
+        Lz77Params {
+            finder: MatchFinderConfig {
+                window_size,
+                chain_depth,
+                min_match,
+                max_match: MAX_MATCH,
+            },
+            parsing,
+        }
+    }
+}
+
+fn byte_token_config() -> MatchFinderConfig {
+    MatchFinderConfig {
+        window_size: MAX_OFFSET,
+        chain_depth: CHAIN_DEPTH,
+        min_match: MIN_MATCH,
+        max_match: MAX_LENGTH,
+    }
+}
+
+pub fn tokenize(data: &[u8], params: &Lz77Params) -> Vec<Token> {
+    match params.parsing {
+        Parsing::Greedy => parse_greedy(data, params.finder),
+        Parsing::Lazy => parse_lazy(data, params.finder),
+        Parsing::Optimal => parse_optimal(data, params.finder),
+    }
+}
+
+fn parse_greedy(data: &[u8], config: MatchFinderConfig) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut finder = MatchFinder::new(data, config);
     let mut i = 0;
 
     while i < data.len() {
-        let mut match_offset = 0;
-        let mut match_length = 0;
-
-        for offset in 1..=WINDOW_SIZE.min(i) {
-            let mut length = 0;
-            while i + length < data.len()
-                && data[i - offset + length] == data[i + length]
-                && length < LOOKAHEAD_SIZE
-            {
-                length += 1;
+        match finder.find(i) {
+            Some(m) => {
+                tokens.push(Token::Match { offset: m.offset, length: m.length });
+                for pos in i..i + m.length {
+                    finder.insert(pos);
+                }
+                i += m.length;
+            }
+            None => {
+                tokens.push(Token::Literal(data[i]));
+                finder.insert(i);
+                i += 1;
+            }
+        }
+    }
+
+    tokens
+}
+
+fn parse_lazy(data: &[u8], config: MatchFinderConfig) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut finder = MatchFinder::new(data, config);
+    let mut i = 0;
+    let mut pending = None;
+
+    while i < data.len() {
+        let current = pending.take().or_else(|| finder.find(i));
+        finder.insert(i);
+
+        let Some(m) = current else {
+            tokens.push(Token::Literal(data[i]));
+            i += 1;
+            continue;
+        };
+
+        // A longer match one byte later is worth a literal now.
+        if m.length < NICE_LENGTH && i + 1 < data.len() {
+            if let Some(next) = finder.find(i + 1) {
+                if next.length > m.length {
+                    tokens.push(Token::Literal(data[i]));
+                    pending = Some(next);
+                    i += 1;
+                    continue;
+                }
+            }
+        }
+
+        tokens.push(Token::Match { offset: m.offset, length: m.length });
+        for pos in i + 1..i + m.length {
+            finder.insert(pos);
+        }
+        i += m.length;
+    }
+
+    tokens
+}
+
+fn varint_len(value: usize) -> u32 {
+    let bits = usize::BITS - value.leading_zeros();
+    bits.div_ceil(7).max(1)
+}
+
+// Encoded size in bits, including the token's flag bit.
+fn literal_cost() -> u32 {
+    9
+}
+
+fn match_cost(offset: usize, length: usize) -> u32 {
+    1 + 8 * (varint_len(offset) + varint_len(length - MIN_MATCH))
+}
+
+// The optimal parse keeps a price and a step for every position it covers,
+// so it runs over windows of this many bytes to bound its memory. Matches
+// still reach back into earlier windows. u32 prices can't overflow within a
+// window.
+const PARSE_WINDOW: usize = 1 << 20;
+
+fn parse_optimal(data: &[u8], config: MatchFinderConfig) -> Vec<Token> {
+    let mut finder = MatchFinder::new(data, config);
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + PARSE_WINDOW).min(data.len());
+        parse_window(data, start, end, &mut finder, config.min_match, &mut tokens);
+        start = end;
+    }
+    tokens
+}
+
+// Finds the cheapest tokens for data[start..end], cutting matches off at
+// `end`.
+fn parse_window(
+    data: &[u8],
+    start: usize,
+    end: usize,
+    finder: &mut MatchFinder,
+    min_match: usize,
+    tokens: &mut Vec<Token>,
+) {
+    let n = end - start;
+
+    // price[i] is the cheapest cost of encoding data[start..start + i];
+    // step[i] is the token that reaches i on that path, as (length, offset)
+    // with offset 0 for a literal.
+    let mut price = vec![u32::MAX; n + 1];
+    let mut step = vec![(0usize, 0usize); n + 1];
+    price[0] = 0;
+
+    let mut i = 0;
+    while i < n {
+        let base = price[i];
+        if base + literal_cost() < price[i + 1] {
+            price[i + 1] = base + literal_cost();
+            step[i + 1] = (1, 0);
+        }
+
+        let matches = finder.find_all(start + i);
+        finder.insert(start + i);
+
+        if let Some(longest) = matches.last().copied().filter(|m| m.length.min(n - i) >= NICE_LENGTH) {
+            let length = longest.length.min(n - i);
+            let cost = base + match_cost(longest.offset, length);
+            if cost < price[i + length] {
+                price[i + length] = cost;
+                step[i + length] = (length, longest.offset);
+            }
+            for pos in i + 1..i + length {
+                finder.insert(start + pos);
             }
+            i += length;
+            continue;
+        }
 
-            if length > match_length {
-                match_length = length;
-                match_offset = offset;
+        let mut shortest = min_match;
+        for m in &matches {
+            for length in shortest..=m.length.min(n - i) {
+                let cost = base + match_cost(m.offset, length);
+                if cost < price[i + length] {
+                    price[i + length] = cost;
+                    step[i + length] = (length, m.offset);
+                }
             }
+            shortest = m.length + 1;
         }
+        i += 1;
+    }
 
-        if match_length >= 3 {
-            output.push(1);
-            output.push(match_offset as u8);
-            output.push(match_length as u8);
-            i += match_length;
+    let first = tokens.len();
+    let mut pos = n;
+    while pos > 0 {
+        let (length, offset) = step[pos];
+        pos -= length;
+        if offset == 0 {
+            tokens.push(Token::Literal(data[start + pos]));
         } else {
-            output.push(0);
-            output.push(data[i]);
-            i += 1;
+            tokens.push(Token::Match { offset, length });
+        }
+    }
+    tokens[first..].reverse();
+}
+
+pub fn write_tokens(tokens: &[Token]) -> Vec<u8> {
+    let mut output = Vec::new();
+
+    for group in tokens.chunks(8) {
+        let flag_pos = output.len();
+        output.push(0);
+        for (bit, token) in group.iter().enumerate() {
+            match *token {
+                Token::Literal(byte) => output.push(byte),
+                Token::Match { offset, length } => {
+                    output[flag_pos] |= 1 << bit;
+                    leb128::write::unsigned(&mut output, offset as u64).unwrap();
+                    leb128::write::unsigned(&mut output, (length - MIN_MATCH) as u64).unwrap();
+                }
+            }
+        }
+    }
+
+    output
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<usize, String> {
+    let value = leb128::read::unsigned(input).map_err(|e| match e {
+        leb128::read::Error::IoError(_) => "Unexpected end of data in match".to_string(),
+        leb128::read::Error::Overflow => "Varint overflow in match".to_string(),
+    })?;
+    usize::try_from(value).map_err(|_| format!("Match value {} out of range", value))
+}
+
+pub fn compress_lz77(data: &[u8]) -> Result<Vec<u8>, String> {
+    compress_lz77_with_level(data, DEFAULT_LEVEL)
+}
+
+pub fn compress_lz77_with_level(data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+    check_level(level)?;
+    Ok(write_tokens(&tokenize(data, &Lz77Params::for_level(level))))
+}
+
+// `limit` is the most output the caller expects, normally the size recorded
+// in the stream header or block. Corrupt or hostile matches that would go
+// past it are rejected before anything is allocated for them.
+pub fn decompress_lz77(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut input = data;
+
+    while let Some((&flags, rest)) = input.split_first() {
+        input = rest;
+        for bit in 0..8 {
+            if input.is_empty() {
+                break;
+            }
+            if flags & (1 << bit) == 0 {
+                if output.len() == limit {
+                    return Err(format!("Output exceeds the expected {} bytes", limit));
+                }
+                output.push(input[0]);
+                input = &input[1..];
+                continue;
+            }
+
+            let offset = read_varint(&mut input)?;
+            let length = read_varint(&mut input)?
+                .checked_add(MIN_MATCH)
+                .ok_or_else(|| "Match length out of range".to_string())?;
+            copy_match(&mut output, offset, length, limit)?;
         }
     }
 
     Ok(output)
 }
 
-pub fn decompress_lz77(data: &[u8]) -> Result<Vec<u8>, String> {
+fn copy_match(output: &mut Vec<u8>, offset: usize, length: usize, limit: usize) -> Result<(), String> {
+    if offset == 0 || output.len() < offset {
+        return Err(format!(
+            "Invalid offset in match: offset={}, length={}, output_len={}",
+            offset, length, output.len()
+        ));
+    }
+    if length > limit - output.len() {
+        return Err(format!(
+            "Match of {} bytes at output offset {} exceeds the expected {} bytes",
+            length,
+            output.len(),
+            limit
+        ));
+    }
+
+    // The source may overlap the bytes being produced, so copy one byte at
+    // a time.
+    let start = output.len() - offset;
+    output.try_reserve(length).map_err(|e| format!("Match of {} bytes: {}", length, e))?;
+    for j in 0..length {
+        output.push(output[start + j]);
+    }
+    Ok(())
+}
+
+// Decodes the original single-byte token format: a 0 marker followed by a
+// literal, or a 1 marker followed by one-byte offset and length.
+pub fn decompress_lz77_legacy(data: &[u8]) -> Result<Vec<u8>, String> {
     let mut output = Vec::new();
     let mut i = 0;
 
@@ -59,21 +385,9 @@ pub fn decompress_lz77(data: &[u8]) -> Result<Vec<u8>, String> {
                 }
                 let offset = data[i + 1] as usize;
                 let length = data[i + 2] as usize;
-
-                if offset == 0 || output.len() < offset {
-                    return Err("Invalid offset in match".to_string());
-                }
-
-                for j in 0..length {
-                    if output.len() < offset || output.len() - offset + j >= output.len() {
-                        return Err(format!(
-                            "Invalid offset or length in match: offset={}, length={}, output_len={}",
-                            offset, length, output.len()
-                        ));
-                    }
-                    output.push(output[output.len() - offset + j]);
-                }
-
+                // Headerless data has no recorded size; a single-byte length
+                // can't blow up anyway.
+                copy_match(&mut output, offset, length, usize::MAX)?;
                 i += 3;
             }
             _ => return Err("Invalid prefix byte".to_string()),
@@ -82,6 +396,7 @@ pub fn decompress_lz77(data: &[u8]) -> Result<Vec<u8>, String> {
 
     Ok(output)
 }
+
 #[derive(Debug)]
 pub enum CompressionError {
     InvalidOffset(String),
@@ -101,61 +416,47 @@ impl std::fmt::Display for CompressionError {
     }
 }
 
+// Writes the legacy single-byte token format read by `decompress_lz77_legacy`.
 pub fn compress_lz77_improved(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
     if data.is_empty() {
         return Ok(Vec::new());
     }
 
     let mut output = Vec::with_capacity(data.len());
+    let mut finder = MatchFinder::new(data, MatchFinderConfig {
+        chain_depth: 256,
+        ..byte_token_config()
+    });
     let mut i = 0;
-    let window_size = 255; // Maximum window size that can be encoded in a byte
 
     while i < data.len() {
-        let mut best_match_length = 0;
-        let mut best_match_offset = 0;
-        
-        // Calculate window boundaries
-        let window_start = if i > window_size { i - window_size } else { 0 };
-        let look_ahead_end = std::cmp::min(i + 255, data.len()); // Max length we can encode
-        
-        // Search for matches in the window
-        for j in window_start..i {
-            let mut current_length = 0;
-            let mut j_offset = 0;
-            
-            while i + current_length < look_ahead_end 
-                && j + j_offset < i 
-                && data[i + current_length] == data[j + (j_offset % (i - j))] {
-                current_length += 1;
-                j_offset += 1;
-            }
-
-            if current_length > best_match_length {
-                best_match_length = current_length;
-                best_match_offset = i - j;
-            }
-        }
+        match finder.find(i) {
+            Some(m) => {
+                if m.offset > MAX_OFFSET {
+                    return Err(CompressionError::InvalidOffset(
+                        "Match offset exceeds maximum value".to_string()
+                    ));
+                }
+                if m.length > MAX_LENGTH {
+                    return Err(CompressionError::InvalidLength(
+                        "Match length exceeds maximum value".to_string()
+                    ));
+                }
 
-        if best_match_length > 2 { // Only use matches if they save space
-            if best_match_offset > 255 {
-                return Err(CompressionError::InvalidOffset(
-                    "Match offset exceeds maximum value".to_string()
-                ));
+                output.push(1); // Match marker
+                output.push(m.offset as u8);
+                output.push(m.length as u8);
+                for pos in i..i + m.length {
+                    finder.insert(pos);
+                }
+                i += m.length;
             }
-            if best_match_length > 255 {
-                return Err(CompressionError::InvalidLength(
-                    "Match length exceeds maximum value".to_string()
-                ));
+            None => {
+                output.push(0); // Literal marker
+                output.push(data[i]);
+                finder.insert(i);
+                i += 1;
             }
-            
-            output.push(1); // Match marker
-            output.push(best_match_offset as u8);
-            output.push(best_match_length as u8);
-            i += best_match_length;
-        } else {
-            output.push(0); // Literal marker
-            output.push(data[i]);
-            i += 1;
         }
     }
 
@@ -170,7 +471,7 @@ mod tests {
     fn test_empty_input() {
         let input = &[];
         let compressed = compress_lz77_improved(input).unwrap();
-        let decompressed = decompress_lz77(&compressed).unwrap();
+        let decompressed = decompress_lz77_legacy(&compressed).unwrap();
         assert_eq!(input.to_vec(), decompressed);
     }
 
@@ -178,47 +479,172 @@ mod tests {
     fn test_single_byte() {
         let input = &[42];
         let compressed = compress_lz77_improved(input).unwrap();
-        let decompressed = decompress_lz77(&compressed).unwrap();
+        let decompressed = decompress_lz77_legacy(&compressed).unwrap();
         assert_eq!(input.to_vec(), decompressed);
     }
 
-    // #[test]
-    // fn test_repeated_sequence() {
-    //     let input = b"ABABABABABAB";
-    //     let compressed = compress_lz77_improved(input).unwrap();
-    //     let decompressed = decompress_lz77(&compressed).unwrap();
-    //     assert_eq!(input.to_vec(), decompressed);
-    // }
+    #[test]
+    fn test_repeated_sequence() {
+        let input = b"ABABABABABAB";
+        let compressed = compress_lz77_improved(input).unwrap();
+        let decompressed = decompress_lz77_legacy(&compressed).unwrap();
+        assert_eq!(input.to_vec(), decompressed);
+    }
 
     #[test]
     fn test_no_repetition() {
         let input = b"ABCDEFGHIJKLMNOP";
         let compressed = compress_lz77_improved(input).unwrap();
-        let decompressed = decompress_lz77(&compressed).unwrap();
+        let decompressed = decompress_lz77_legacy(&compressed).unwrap();
         assert_eq!(input.to_vec(), decompressed);
     }
 
-    // #[test]
-    // fn test_long_repetition() {
-    //     let mut input = Vec::new();
-    //     for _ in 0..1000 {
-    //         input.extend_from_slice(b"ABC");
-    //     }
-    //     let compressed = compress_lz77_improved(&input).unwrap();
-    //     let decompressed = decompress_lz77(&compressed).unwrap();
-    //     assert_eq!(input, decompressed);
-    // }
+    #[test]
+    fn test_long_repetition() {
+        let mut input = Vec::new();
+        for _ in 0..1000 {
+            input.extend_from_slice(b"ABC");
+        }
+        let compressed = compress_lz77_improved(&input).unwrap();
+        let decompressed = decompress_lz77_legacy(&compressed).unwrap();
+        assert_eq!(input, decompressed);
+    }
+
+    #[test]
+    fn test_compress_lz77_round_trip_text() {
+        let input = b"INFO request served in 12ms\nINFO request served in 15ms\n".repeat(50);
+        let compressed = compress_lz77(&input).unwrap();
+        assert!(compressed.len() < input.len() / 4);
+        assert_eq!(decompress_lz77(&compressed, usize::MAX).unwrap(), input);
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        let input = b"ABCDEFGHIJKLMNOP ABCDEFGHIJKLMNOP ABCDEFGHIJKLMNOP";
+        let compressed = compress_lz77(input).unwrap();
+        assert!(compressed.len() < input.len());
+        assert_eq!(decompress_lz77(&compressed, usize::MAX).unwrap(), input.to_vec());
+    }
+
+    #[test]
+    fn test_no_repetition_overhead() {
+        // One flag byte per eight literals instead of a marker per literal.
+        let input = b"ABCDEFGHIJKLMNOP";
+        let compressed = compress_lz77(input).unwrap();
+        assert_eq!(compressed.len(), input.len() + 2);
+        assert_eq!(decompress_lz77(&compressed, usize::MAX).unwrap(), input.to_vec());
+    }
+
+    #[test]
+    fn test_far_offsets_and_long_matches() {
+        let block: Vec<u8> = (0..20_000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let mut input = block.clone();
+        input.extend(std::iter::repeat_n(b'z', 5000));
+        input.extend_from_slice(&block);
+
+        let tokens = tokenize(&input, &Lz77Params::for_level(DEFAULT_LEVEL));
+        assert!(tokens.iter().any(|t| matches!(*t, Token::Match { offset, .. } if offset > 255)));
+        assert!(tokens.iter().any(|t| matches!(*t, Token::Match { length, .. } if length > 255)));
+
+        let compressed = write_tokens(&tokens);
+        assert!(compressed.len() < block.len() + 100);
+        assert_eq!(decompress_lz77(&compressed, usize::MAX).unwrap(), input);
+    }
+
+    #[test]
+    fn test_every_level_round_trips() {
+        let mut input = Vec::new();
+        for i in 0..400 {
+            input.extend_from_slice(format!("line {} status=ok path=/api/v1/items/{}\n", i % 17, i % 5).as_bytes());
+        }
+        input.extend(std::iter::repeat_n(b'-', 1000));
+
+        for level in MIN_LEVEL..=MAX_LEVEL {
+            let compressed = compress_lz77_with_level(&input, level).unwrap();
+            assert_eq!(decompress_lz77(&compressed, usize::MAX).unwrap(), input, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn test_higher_levels_compress_better() {
+        let mut input = Vec::new();
+        for i in 0..2000u32 {
+            input.extend_from_slice(format!("{} {} ", i % 97, (i * 7) % 13).as_bytes());
+        }
+        let fastest = compress_lz77_with_level(&input, MIN_LEVEL).unwrap();
+        let default = compress_lz77_with_level(&input, DEFAULT_LEVEL).unwrap();
+        let best = compress_lz77_with_level(&input, MAX_LEVEL).unwrap();
+        assert!(default.len() <= fastest.len());
+        assert!(best.len() <= default.len());
+    }
+
+    #[test]
+    fn test_optimal_parse_not_larger_than_greedy() {
+        let input = b"abcabcabdabcabcabdxyzabcabdabcabc".repeat(20);
+        let params = Lz77Params::for_level(MAX_LEVEL);
+        let greedy = write_tokens(&parse_greedy(&input, params.finder));
+        let optimal = write_tokens(&parse_optimal(&input, params.finder));
+        assert!(optimal.len() <= greedy.len());
+        assert_eq!(decompress_lz77(&optimal, usize::MAX).unwrap(), input);
+    }
+
+    #[test]
+    fn test_optimal_parse_across_windows() {
+        // Matches reach back into the previous window and get cut off at
+        // the end of each one
+        let mut input: Vec<u8> = (0..PARSE_WINDOW as u32 + 5000).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        input.extend_from_within(PARSE_WINDOW - 3000..PARSE_WINDOW + 1000);
+        input.extend_from_within(..PARSE_WINDOW);
+        let params = Lz77Params::for_level(MAX_LEVEL);
+        let optimal = write_tokens(&parse_optimal(&input, params.finder));
+        assert!(optimal.len() < input.len() / 2);
+        assert_eq!(decompress_lz77(&optimal, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        assert!(compress_lz77_with_level(b"abc", 0).is_err());
+        assert!(compress_lz77_with_level(b"abc", 10).is_err());
+    }
+
+    #[test]
+    fn test_truncated_match() {
+        let compressed = write_tokens(&[Token::Literal(b'a'), Token::Match { offset: 300, length: 10 }]);
+        assert!(decompress_lz77(&compressed[..compressed.len() - 2], usize::MAX).is_err());
+    }
 
     #[test]
     fn test_invalid_offset() {
-        let compressed = vec![1, 5, 3]; // Offset 5 is invalid for an empty output
-        assert!(decompress_lz77(&compressed).is_err());
+        // Offset 5 reaches before the start of a one-byte output
+        let compressed = write_tokens(&[Token::Literal(b'a'), Token::Match { offset: 5, length: 3 }]);
+        let err = decompress_lz77(&compressed, usize::MAX).unwrap_err();
+        assert!(err.contains("Invalid offset"), "{}", err);
+        assert!(decompress_lz77_legacy(&[0, b'a', 1, 5, 3]).is_err());
+    }
+
+    #[test]
+    fn test_match_past_limit() {
+        // A literal, then a match with offset 1 and a length just short of
+        // 2^64; this used to abort on the allocation
+        let mut compressed = vec![0b10, b'a', 1];
+        leb128::write::unsigned(&mut compressed, u64::MAX - MIN_MATCH as u64).unwrap();
+        let err = decompress_lz77(&compressed, 1 << 20).unwrap_err();
+        assert!(err.contains("exceeds"), "{}", err);
+        assert!(decompress_lz77(&compressed, usize::MAX).is_err());
+
+        let compressed = write_tokens(&[Token::Literal(b'a'), Token::Match { offset: 1, length: 10 }]);
+        assert_eq!(decompress_lz77(&compressed, 11).unwrap(), b"a".repeat(11));
+        assert!(decompress_lz77(&compressed, 10).is_err());
+        assert!(decompress_lz77(&write_tokens(&[Token::Literal(b'a'); 3]), 2).is_err());
     }
 
     #[test]
     fn test_invalid_length() {
-        let compressed = vec![1, 1, 255]; // Length 255 is invalid for a small output
-        assert!(decompress_lz77(&compressed).is_err());
+        // A valid offset, but 255 bytes is longer than the expected output
+        let compressed = write_tokens(&[Token::Literal(b'a'), Token::Match { offset: 1, length: 255 }]);
+        let err = decompress_lz77(&compressed, 16).unwrap_err();
+        assert!(err.contains("exceeds the expected 16 bytes"), "{}", err);
+        assert_eq!(decompress_lz77(&compressed, 256).unwrap(), vec![b'a'; 256]);
     }
 }
 
@@ -0,0 +1,259 @@
+// LZ77 followed by canonical Huffman coding, in the spirit of DEFLATE.
+//
+// The LZ77 tokens are split into blocks. Each block starts with a final-block
+// bit and its code tables, followed by the Huffman coded tokens and an
+// end-of-block symbol:
+//
+//   final          1 bit
+//   HLIT           6 bits  number of length codes in use
+//   HDIST          6 bits  number of offset codes in use, minus one
+//   code lengths           see `huffman::write_code_lengths`
+//   symbols ...
+//   end of block
+//
+// The literal/length alphabet holds the 256 byte values, the end-of-block
+// symbol and the length codes; offsets have their own alphabet. Lengths
+// (minus MIN_MATCH) and offsets (minus one) are bucketed into a code plus
+// extra bits, so any 32-bit value has a code.
+
+use super::huffman::{self, BitReader, BitWriter, Decoder, Encoder, MAX_CODE_LENGTH};
+use super::lz77::{self, Lz77Params, Token, MIN_MATCH};
+
+const END_OF_BLOCK: usize = 256;
+const FIRST_LENGTH_CODE: usize = 257;
+const MAX_VALUE_CODES: usize = 64;
+const BLOCK_TOKENS: usize = 1 << 16;
+
+// Splits `value` into (code, extra bit count, extra bits). Values 0-3 have
+// their own codes; after that each power of two is covered by two codes.
+fn bucket(value: usize) -> (usize, u32, u32) {
+    if value < 4 {
+        return (value, 0, 0);
+    }
+    let n = usize::BITS - 1 - value.leading_zeros();
+    let extra_bits = n - 1;
+    let code = 2 * n as usize + ((value >> extra_bits) & 1);
+    (code, extra_bits, (value & ((1 << extra_bits) - 1)) as u32)
+}
+
+fn unbucket(code: usize, reader: &mut BitReader) -> Result<usize, String> {
+    if code < 4 {
+        return Ok(code);
+    }
+    if code >= MAX_VALUE_CODES {
+        return Err(format!("Invalid length or offset code {}", code));
+    }
+    let n = (code / 2) as u32;
+    let extra_bits = n - 1;
+    let base = (2 | (code & 1)) << extra_bits;
+    Ok(base + reader.read_bits(extra_bits)? as usize)
+}
+
+pub fn compress_lzh(data: &[u8]) -> Result<Vec<u8>, String> {
+    compress_lzh_with_level(data, lz77::DEFAULT_LEVEL)
+}
+
+pub fn compress_lzh_with_level(data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+    lz77::check_level(level)?;
+
+    let tokens = lz77::tokenize(data, &Lz77Params::for_level(level));
+    let mut writer = BitWriter::new();
+
+    if tokens.is_empty() {
+        write_block(&mut writer, &[], true);
+    }
+    let blocks = tokens.chunks(BLOCK_TOKENS);
+    let count = blocks.len();
+    for (index, block) in blocks.enumerate() {
+        write_block(&mut writer, block, index + 1 == count);
+    }
+
+    Ok(writer.finish())
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], last: bool) {
+    let mut lit_freqs = vec![0u32; FIRST_LENGTH_CODE + MAX_VALUE_CODES];
+    let mut dist_freqs = vec![0u32; MAX_VALUE_CODES];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => lit_freqs[byte as usize] += 1,
+            Token::Match { offset, length } => {
+                lit_freqs[FIRST_LENGTH_CODE + bucket(length - MIN_MATCH).0] += 1;
+                dist_freqs[bucket(offset - 1).0] += 1;
+            }
+        }
+    }
+    lit_freqs[END_OF_BLOCK] = 1;
+
+    let lit_lengths = huffman::build_code_lengths(&lit_freqs, MAX_CODE_LENGTH);
+    let dist_lengths = huffman::build_code_lengths(&dist_freqs, MAX_CODE_LENGTH);
+
+    let hlit = lit_lengths[FIRST_LENGTH_CODE..]
+        .iter()
+        .rposition(|&l| l > 0)
+        .map_or(0, |p| p + 1);
+    let hdist = dist_lengths.iter().rposition(|&l| l > 0).map_or(1, |p| p + 1);
+
+    writer.write_bits(last as u32, 1);
+    writer.write_bits(hlit as u32, 6);
+    writer.write_bits((hdist - 1) as u32, 6);
+
+    let mut table = lit_lengths[..FIRST_LENGTH_CODE + hlit].to_vec();
+    table.extend_from_slice(&dist_lengths[..hdist]);
+    huffman::write_code_lengths(writer, &table);
+
+    let lit_encoder = Encoder::new(&lit_lengths);
+    let dist_encoder = Encoder::new(&dist_lengths);
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => lit_encoder.write(writer, byte as usize),
+            Token::Match { offset, length } => {
+                let (code, bits, extra) = bucket(length - MIN_MATCH);
+                lit_encoder.write(writer, FIRST_LENGTH_CODE + code);
+                writer.write_bits(extra, bits);
+
+                let (code, bits, extra) = bucket(offset - 1);
+                dist_encoder.write(writer, code);
+                writer.write_bits(extra, bits);
+            }
+        }
+    }
+    lit_encoder.write(writer, END_OF_BLOCK);
+}
+
+// Like `lz77::decompress_lz77`, fails instead of producing more than `limit`
+// bytes.
+pub fn decompress_lzh(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader::new(data);
+    let mut output = Vec::new();
+
+    loop {
+        let last = reader.read_bit()? == 1;
+        let hlit = reader.read_bits(6)? as usize;
+        let hdist = reader.read_bits(6)? as usize + 1;
+        if hlit > MAX_VALUE_CODES || hdist > MAX_VALUE_CODES {
+            return Err("Invalid LZH block header".to_string());
+        }
+
+        let table = huffman::read_code_lengths(&mut reader, FIRST_LENGTH_CODE + hlit + hdist)?;
+        let lit_decoder = Decoder::new(&table[..FIRST_LENGTH_CODE + hlit])?;
+        let dist_decoder = Decoder::new(&table[FIRST_LENGTH_CODE + hlit..])?;
+
+        loop {
+            let symbol = lit_decoder.decode(&mut reader)?;
+            if symbol < END_OF_BLOCK {
+                if output.len() == limit {
+                    return Err(format!("Output exceeds the expected {} bytes", limit));
+                }
+                output.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                break;
+            }
+
+            let length = unbucket(symbol - FIRST_LENGTH_CODE, &mut reader)? + MIN_MATCH;
+            let offset = unbucket(dist_decoder.decode(&mut reader)?, &mut reader)? + 1;
+            if offset > output.len() {
+                return Err(format!(
+                    "Invalid offset in match: offset={}, length={}, output_len={}",
+                    offset, length, output.len()
+                ));
+            }
+            if length > limit - output.len() {
+                return Err(format!(
+                    "Match of {} bytes at output offset {} exceeds the expected {} bytes",
+                    length,
+                    output.len(),
+                    limit
+                ));
+            }
+            let start = output.len() - offset;
+            output.try_reserve(length).map_err(|e| format!("Match of {} bytes: {}", length, e))?;
+            for j in 0..length {
+                output.push(output[start + j]);
+            }
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_round_trip() {
+        for value in (0..5000).chain([65_533, 1 << 20, (1 << 22) + 12_345]) {
+            let (code, bits, extra) = bucket(value);
+            assert!(code < MAX_VALUE_CODES);
+            let mut writer = BitWriter::new();
+            writer.write_bits(extra, bits);
+            let bytes = writer.finish();
+            assert_eq!(unbucket(code, &mut BitReader::new(&bytes)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let compressed = compress_lzh(&[]).unwrap();
+        assert!(decompress_lzh(&compressed, usize::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_single_byte() {
+        let compressed = compress_lzh(&[42]).unwrap();
+        assert_eq!(decompress_lzh(&compressed, usize::MAX).unwrap(), vec![42]);
+    }
+
+    #[test]
+    fn test_text_beats_plain_lz77() {
+        let mut input = Vec::new();
+        for i in 0..500 {
+            input.extend_from_slice(
+                format!("2024-01-{:02} GET /index.html 200 {} bytes from 10.0.0.{}\n", i % 28 + 1, i * 37 % 1000, i % 9)
+                    .as_bytes(),
+            );
+        }
+        let lzh = compress_lzh(&input).unwrap();
+        let lz = lz77::compress_lz77(&input).unwrap();
+        assert!(lzh.len() < lz.len());
+        assert_eq!(decompress_lzh(&lzh, usize::MAX).unwrap(), input);
+    }
+
+    #[test]
+    fn test_literal_only_round_trip() {
+        let input = b"a short sentence with no long repeats but skewed letters".to_vec();
+        let compressed = compress_lzh(&input).unwrap();
+        assert_eq!(decompress_lzh(&compressed, usize::MAX).unwrap(), input);
+    }
+
+    #[test]
+    fn test_multiple_blocks() {
+        let input: Vec<u8> = (0..(BLOCK_TOKENS * 2 + 10) as u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        let compressed = compress_lzh_with_level(&input, 1).unwrap();
+        assert_eq!(decompress_lzh(&compressed, usize::MAX).unwrap(), input);
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let compressed = compress_lzh(b"hello hello hello hello").unwrap();
+        assert!(decompress_lzh(&compressed[..compressed.len() / 2], usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_output_past_limit() {
+        // One literal, then matches covering the rest
+        let input = vec![b'a'; 1000];
+        let compressed = compress_lzh(&input).unwrap();
+        assert_eq!(decompress_lzh(&compressed, 1000).unwrap(), input);
+        let err = decompress_lzh(&compressed, 999).unwrap_err();
+        assert!(err.contains("exceeds"), "{}", err);
+        assert!(decompress_lzh(&compressed, 0).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+// PackBits-style run-length encoding.
+//
+// The output is a sequence of packets, each starting with a header byte n:
+//
+//   0..=127    copy the next n + 1 bytes literally
+//   129..=255  repeat the next byte 257 - n times (2 to 128)
+//   128        reserved, rejected by the decoder
+//
+// Unlike the pair encoding in `rle`, data without runs only costs one header
+// byte per 128 input bytes.
+
+const MAX_LITERAL: usize = 128;
+const MAX_REPEAT: usize = 128;
+
+pub fn compress_packbits(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / MAX_LITERAL + 1);
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && data[i + run] == data[i] && run < MAX_REPEAT {
+            run += 1;
+        }
+
+        // A two-byte run only pays off when it doesn't split a literal packet.
+        let worth_it = run >= 3 || (run == 2 && literal_start == i);
+        if worth_it {
+            flush_literals(&mut output, &data[literal_start..i]);
+            output.push((257 - run) as u8);
+            output.push(data[i]);
+            i += run;
+            literal_start = i;
+        } else {
+            i += run;
+        }
+    }
+    flush_literals(&mut output, &data[literal_start..]);
+
+    Ok(output)
+}
+
+fn flush_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERAL) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+pub fn decompress_packbits(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let header = data[i] as usize;
+        i += 1;
+        match header {
+            0..=127 => {
+                let len = header + 1;
+                if i + len > data.len() {
+                    return Err("Unexpected end of data in literal packet".to_string());
+                }
+                output.extend_from_slice(&data[i..i + len]);
+                i += len;
+            }
+            128 => return Err("Invalid PackBits header byte 128".to_string()),
+            _ => {
+                let byte = *data
+                    .get(i)
+                    .ok_or_else(|| "Unexpected end of data in repeat packet".to_string())?;
+                output.extend(std::iter::repeat_n(byte, 257 - header));
+                i += 1;
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_input() {
+        let compressed = compress_packbits(&[]).unwrap();
+        assert!(compressed.is_empty());
+        assert!(decompress_packbits(&compressed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_no_repetition_overhead() {
+        let input = b"ABCDEFGHIJKLMNOP";
+        let compressed = compress_packbits(input).unwrap();
+        assert_eq!(compressed.len(), input.len() + 1);
+        assert_eq!(decompress_packbits(&compressed).unwrap(), input.to_vec());
+    }
+
+    #[test]
+    fn test_repeated_sequence() {
+        let input = b"AAABBBCCCCCDDDDE";
+        let compressed = compress_packbits(input).unwrap();
+        assert!(compressed.len() < input.len());
+        assert_eq!(decompress_packbits(&compressed).unwrap(), input.to_vec());
+    }
+
+    #[test]
+    fn test_mixed_runs_and_literals() {
+        let mut input = Vec::new();
+        for i in 0..2000u32 {
+            input.push((i.wrapping_mul(2654435761) >> 24) as u8);
+            if i % 50 == 0 {
+                input.extend(std::iter::repeat_n(0u8, 300));
+            }
+        }
+        let compressed = compress_packbits(&input).unwrap();
+        assert_eq!(decompress_packbits(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_worst_case_bound() {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = compress_packbits(&input).unwrap();
+        assert!(compressed.len() <= input.len() + input.len().div_ceil(MAX_LITERAL));
+        assert_eq!(decompress_packbits(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        assert!(decompress_packbits(&[128]).is_err());
+        assert!(decompress_packbits(&[3, b'a']).is_err());
+        assert!(decompress_packbits(&[0xfe]).is_err());
+    }
+}
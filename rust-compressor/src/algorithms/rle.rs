@@ -1,36 +1,42 @@
+// Each pair is (byte, count) with a count of 1-255. Longer runs are split
+// into several pairs, so any run length round-trips.
+const MAX_RUN: usize = 255;
+
 pub fn compress_rle(data: &[u8]) -> Result<Vec<u8>, String> {
     if data.is_empty() {
         return Ok(Vec::new());
     }
 
     let mut compressed = Vec::new();
-    let mut count: u16 = 1; // use u16 to support larger runs
-
-    for i in 1..data.len() {
-        if data[i] == data[i - 1] && count < u16::MAX {
-            count += 1;
-        } else {
-            compressed.push(data[i - 1]);
-            compressed.push(count as u8);
-            count = 1;
+    let mut i = 0;
+
+    while i < data.len() {
+        let byte = data[i];
+        let mut run = 1;
+        while i + run < data.len() && data[i + run] == byte && run < MAX_RUN {
+            run += 1;
         }
+        compressed.push(byte);
+        compressed.push(run as u8);
+        i += run;
     }
 
-    compressed.push(data[data.len() - 1]);
-    compressed.push(count as u8);
     Ok(compressed)
 }
 
 pub fn decompress_rle(data: &[u8]) -> Result<Vec<u8>, String> {
-    if data.len() % 2 != 0 {
+    if !data.len().is_multiple_of(2) {
         return Err("Invalid RLE data: odd length".to_string());
     }
 
     let mut output = Vec::new();
-    for chunk in data.chunks(2) {
+    for (index, chunk) in data.chunks(2).enumerate() {
         let byte = chunk[0];
         let count = chunk[1] as usize;
-        output.extend(std::iter::repeat(byte).take(count));
+        if count == 0 {
+            return Err(format!("Invalid RLE data: zero run length at offset {}", index * 2 + 1));
+        }
+        output.extend(std::iter::repeat_n(byte, count));
     }
 
     Ok(output)
@@ -83,6 +89,49 @@ mod tests {
         assert_eq!(input, decompressed);
     }
 
+    fn assert_run_round_trips(length: usize) {
+        let input = vec![b'A'; length];
+        let compressed = compress_rle(&input).unwrap();
+        assert_eq!(compressed.len(), 2 * length.div_ceil(255));
+        let decompressed = decompress_rle(&compressed).unwrap();
+        assert_eq!(decompressed.len(), length);
+        assert_eq!(input, decompressed);
+    }
+
+    #[test]
+    fn test_run_of_255() {
+        assert_run_round_trips(255);
+    }
+
+    #[test]
+    fn test_run_of_256() {
+        assert_run_round_trips(256);
+    }
+
+    #[test]
+    fn test_run_of_65535() {
+        assert_run_round_trips(65_535);
+    }
+
+    #[test]
+    fn test_run_of_one_million() {
+        assert_run_round_trips(1_000_000);
+    }
+
+    #[test]
+    fn test_long_runs_between_other_bytes() {
+        let mut input = vec![b'x'];
+        input.extend(std::iter::repeat_n(b'y', 70_000));
+        input.push(b'z');
+        let compressed = compress_rle(&input).unwrap();
+        assert_eq!(decompress_rle(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_zero_count_rejected() {
+        assert!(decompress_rle(&[b'A', 3, b'B', 0]).is_err());
+    }
+
     #[test]
     fn test_invalid_compressed_data() {
         let invalid_data = vec![1, 2, 3]; // Odd length
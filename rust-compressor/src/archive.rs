@@ -0,0 +1,1868 @@
+// Multi-file archive container.
+//
+// Layout (all integers little-endian):
+//
+//   magic          4 bytes  "RZAR"
+//   version        1 byte
+//   entry count    8 bytes
+//   entries, each:
+//     path length  4 bytes
+//     path         UTF-8, relative, components separated by '/'
+//     type         1 byte   0 file, 1 directory, 2 symlink, 3 hard link
+//     target       links only: length (4 bytes), then the UTF-8 target
+//     metadata     mode, mtime and ownership, see `io::metadata`
+//     checksum     4 bytes  CRC32 of the uncompressed entry
+//     size         8 bytes  uncompressed size
+//     data length  8 bytes
+//     data         a blocked stream as written by `io::stream::CompressWriter`;
+//                  for anything but files, a stream holding no data (just
+//                  the header and the end marker, 35 bytes)
+//   index, one record per entry:
+//     path length  4 bytes
+//     path
+//     type and target
+//     metadata
+//     offset       8 bytes  position of the entry's record above
+//     size         8 bytes  uncompressed size
+//     data length  8 bytes
+//     codec id     1 byte
+//     checksum     4 bytes
+//   footer:
+//     index offset 8 bytes
+//     entry count  8 bytes
+//     magic        4 bytes  "RZIX"
+//
+// The entries can be read front to back without the index; the index lets
+// `Archive` jump straight to a single entry. Entries are compressed straight
+// from their source, so the checksum, size and data length of a record and
+// the entry count in the header are placeholders that `ArchiveWriter` fills
+// in by seeking back once they are known.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use walkdir::WalkDir;
+
+use crate::detect;
+use crate::format::{self, Header};
+use crate::io::metadata::{EntryMetadata, RestoreOptions};
+use crate::io::stream::CompressWriter;
+use crate::Algorithm;
+
+pub const MAGIC: [u8; 4] = *b"RZAR";
+pub const ARCHIVE_VERSION: u8 = 6;
+pub const INDEX_MAGIC: [u8; 4] = *b"RZIX";
+const HEADER_LEN: u64 = 13;
+const FOOTER_LEN: u64 = 20;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub fn has_magic(data: &[u8]) -> bool {
+    data.len() >= MAGIC.len() && data[..MAGIC.len()] == MAGIC
+}
+
+// Checks whether the file at `path` starts with the archive magic bytes.
+pub fn is_archive(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(has_magic(&magic)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Turns a filesystem path into the name stored in the archive: only normal
+// components are kept, so roots, `.` and `..` are dropped, and components
+// are joined with '/'.
+pub fn archive_path(path: &Path) -> io::Result<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        if let Component::Normal(part) = component {
+            let part = part.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Path is not valid UTF-8: {}", path.display()),
+                )
+            })?;
+            parts.push(part);
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+// What an archive entry is. Link targets are stored as given: a symlink's
+// target is the path it points to, a hard link's target is the name of an
+// earlier file entry in the same archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink(String),
+    HardLink(String),
+}
+
+impl EntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Directory => "directory",
+            EntryKind::Symlink(_) => "symlink",
+            EntryKind::HardLink(_) => "hardlink",
+        }
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        match self {
+            EntryKind::Symlink(target) | EntryKind::HardLink(target) => Some(target),
+            EntryKind::File | EntryKind::Directory => None,
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let id = match self {
+            EntryKind::File => 0u8,
+            EntryKind::Directory => 1,
+            EntryKind::Symlink(_) => 2,
+            EntryKind::HardLink(_) => 3,
+        };
+        writer.write_all(&[id])?;
+        if let Some(target) = self.target() {
+            writer.write_all(&(target.len() as u32).to_le_bytes())?;
+            writer.write_all(target.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Reads the type and target. Errors are plain messages for the caller
+    // to locate; truncation comes back as `UnexpectedEof`.
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut id = [0u8; 1];
+        reader.read_exact(&mut id)?;
+        let read_target = |reader: &mut R| -> io::Result<String> {
+            let len = read_u32(reader)?;
+            let mut target = Vec::new();
+            reader.take(len as u64).read_to_end(&mut target)?;
+            if target.len() != len as usize {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            String::from_utf8(target).map_err(|_| invalid_data("link target is not valid UTF-8".to_string()))
+        };
+        match id[0] {
+            0 => Ok(EntryKind::File),
+            1 => Ok(EntryKind::Directory),
+            2 => Ok(EntryKind::Symlink(read_target(reader)?)),
+            3 => Ok(EntryKind::HardLink(read_target(reader)?)),
+            other => Err(invalid_data(format!("unknown entry type {}", other))),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        1 + self.target().map_or(0, |target| 4 + target.len())
+    }
+}
+
+// A file, directory or link found among the inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub kind: EntryKind,
+    pub metadata: EntryMetadata,
+}
+
+// Expands the inputs into the entries to store. Files and links keep their
+// path as given; directories are walked recursively and stored, along with
+// everything in them, under the directory's own path. Symlinks are stored
+// as links rather than followed, and files reachable under several names
+// are stored once, with hard links for the other names. Other special
+// files such as sockets and devices are left out.
+pub fn collect_entries(input_paths: &[String]) -> io::Result<Vec<SourceEntry>> {
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    // First name stored for each file with several links, by device and inode
+    let mut links = HashMap::new();
+
+    for input_path in input_paths {
+        let input = Path::new(input_path);
+        let mut found = Vec::new();
+        if input.is_dir() {
+            for entry in WalkDir::new(input).sort_by_file_name() {
+                let entry = entry.map_err(io::Error::other)?;
+                let meta = entry.metadata().map_err(io::Error::other)?;
+                found.push((entry.into_path(), meta));
+            }
+        } else {
+            found.push((input.to_path_buf(), std::fs::symlink_metadata(input)?));
+        }
+
+        for (path, meta) in found {
+            let name = archive_path(&path)?;
+            if name.is_empty() {
+                // An input like `.` stores its contents, not itself.
+                if meta.is_dir() {
+                    continue;
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Can't derive an archive path from {}", path.display()),
+                ));
+            }
+            let Some(kind) = source_kind(&path, &meta, &name, &mut links)? else {
+                continue;
+            };
+            if !seen.insert(name.clone()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Duplicate archive entry: {}", name),
+                ));
+            }
+            let metadata = EntryMetadata::from_fs(&meta);
+            entries.push(SourceEntry { path, name, kind, metadata });
+        }
+    }
+
+    Ok(entries)
+}
+
+fn source_kind(
+    path: &Path,
+    meta: &std::fs::Metadata,
+    name: &str,
+    links: &mut HashMap<(u64, u64), String>,
+) -> io::Result<Option<EntryKind>> {
+    let file_type = meta.file_type();
+    if file_type.is_dir() {
+        return Ok(Some(EntryKind::Directory));
+    }
+    if file_type.is_symlink() {
+        let target = std::fs::read_link(path)?;
+        let target = target.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Symlink target is not valid UTF-8: {}", path.display()),
+            )
+        })?;
+        return Ok(Some(EntryKind::Symlink(target.to_string())));
+    }
+    if !file_type.is_file() {
+        return Ok(None);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if meta.nlink() > 1 {
+            if let Some(first) = links.get(&(meta.dev(), meta.ino())) {
+                return Ok(Some(EntryKind::HardLink(first.clone())));
+            }
+            links.insert((meta.dev(), meta.ino()), name.to_string());
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (name, links);
+
+    Ok(Some(EntryKind::File))
+}
+
+// Writes an archive entry by entry. Each entry is streamed through a
+// `CompressWriter`, so memory use doesn't depend on the entry size.
+pub struct ArchiveWriter<W: Write + Seek> {
+    inner: W,
+    algorithm: Algorithm,
+    level: u32,
+    index: Vec<EntryInfo>,
+    // Position of each entry in `index`, by name
+    names: HashMap<String, usize>,
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    pub fn new(mut inner: W, algorithm: Algorithm, level: u32) -> io::Result<Self> {
+        crate::check_level(level).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        inner.write_all(&MAGIC)?;
+        inner.write_all(&[ARCHIVE_VERSION])?;
+        inner.write_all(&0u64.to_le_bytes())?;
+        Ok(ArchiveWriter {
+            inner,
+            algorithm,
+            level,
+            index: Vec::new(),
+            names: HashMap::new(),
+        })
+    }
+
+    // Compresses the file at `path` as the entry `name`, keeping its mode,
+    // modification time and ownership.
+    pub fn add_file(&mut self, name: &str, path: &Path) -> io::Result<()> {
+        let file = File::open(path)?;
+        let metadata = EntryMetadata::from_fs(&file.metadata()?);
+        self.add(name, &metadata, io::BufReader::new(file))
+    }
+
+    // Compresses everything from `reader` as the file entry `name`. Data
+    // that starts like an already compressed format (see
+    // `detect::SIGNATURES`) is stored without trying the codec.
+    pub fn add<R: Read>(&mut self, name: &str, metadata: &EntryMetadata, reader: R) -> io::Result<()> {
+        let reader = crate::peek(reader, detect::MAX_SIGNATURE_LEN)?;
+        let algorithm = match detect::sniff(reader.get_ref().0.get_ref()) {
+            Some(_) => Algorithm::Stored,
+            None => self.algorithm,
+        };
+        self.add_entry(name, EntryKind::File, algorithm, metadata, reader)
+    }
+
+    pub fn add_directory(&mut self, name: &str, metadata: &EntryMetadata) -> io::Result<()> {
+        self.add_entry(name, EntryKind::Directory, self.algorithm, metadata, io::empty())
+    }
+
+    pub fn add_symlink(&mut self, name: &str, target: &str, metadata: &EntryMetadata) -> io::Result<()> {
+        self.add_entry(name, EntryKind::Symlink(target.to_string()), self.algorithm, metadata, io::empty())
+    }
+
+    // Stores `name` as another name for `target`, a file added earlier.
+    pub fn add_hard_link(&mut self, name: &str, target: &str, metadata: &EntryMetadata) -> io::Result<()> {
+        let is_file = self.names.get(target).is_some_and(|&i| self.index[i].kind == EntryKind::File);
+        if !is_file {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Hard link {} points to {}, which isn't a file in the archive", name, target),
+            ));
+        }
+        self.add_entry(name, EntryKind::HardLink(target.to_string()), self.algorithm, metadata, io::empty())
+    }
+
+    fn add_entry<R: Read>(
+        &mut self,
+        name: &str,
+        kind: EntryKind,
+        algorithm: Algorithm,
+        metadata: &EntryMetadata,
+        mut reader: R,
+    ) -> io::Result<()> {
+        if self.names.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Duplicate archive entry: {}", name),
+            ));
+        }
+
+        // Write the record with placeholders for what isn't known yet
+        let offset = self.inner.stream_position()?;
+        self.inner.write_all(&(name.len() as u32).to_le_bytes())?;
+        self.inner.write_all(name.as_bytes())?;
+        kind.write_to(&mut self.inner)?;
+        metadata.write_to(&mut self.inner)?;
+        let fields = self.inner.stream_position()?;
+        self.inner.write_all(&[0u8; 4 + 8 + 8])?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        let mut original_size = 0u64;
+        let mut encoder = CompressWriter::new(&mut self.inner, algorithm, self.level);
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hasher.update(&buf[..n]);
+            original_size += n as u64;
+            encoder.write_all(&buf[..n])?;
+        }
+        encoder.finish()?;
+
+        let end = self.inner.stream_position()?;
+        let compressed_size = end - fields - (4 + 8 + 8);
+        let checksum = hasher.finalize();
+        self.inner.seek(SeekFrom::Start(fields))?;
+        self.inner.write_all(&checksum.to_le_bytes())?;
+        self.inner.write_all(&original_size.to_le_bytes())?;
+        self.inner.write_all(&compressed_size.to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(end))?;
+
+        self.names.insert(name.to_string(), self.index.len());
+        self.index.push(EntryInfo {
+            path: name.to_string(),
+            kind,
+            algorithm,
+            original_size,
+            compressed_size,
+            checksum,
+            offset,
+            metadata: metadata.clone(),
+        });
+        Ok(())
+    }
+
+    // Writes the index, fills in the entry count and returns the inner
+    // writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let index_offset = self.inner.stream_position()?;
+        write_index(&mut self.inner, &self.index, index_offset)?;
+        let end = self.inner.stream_position()?;
+        self.inner.seek(SeekFrom::Start((MAGIC.len() + 1) as u64))?;
+        self.inner.write_all(&(self.index.len() as u64).to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+pub fn compress_multiple_files(input_paths: &[String], output_path: &str, algorithm: Algorithm, level: u32) -> io::Result<()> {
+    let entries = collect_entries(input_paths)?;
+
+    // Create output file
+    let output_file = io::BufWriter::new(File::create(output_path)?);
+    let mut archive = ArchiveWriter::new(output_file, algorithm, level)?;
+
+    for entry in &entries {
+        let (name, metadata) = (&entry.name, &entry.metadata);
+        match &entry.kind {
+            EntryKind::File => archive.add(name, metadata, io::BufReader::new(File::open(&entry.path)?))?,
+            EntryKind::Directory => archive.add_directory(name, metadata)?,
+            EntryKind::Symlink(target) => archive.add_symlink(name, target, metadata)?,
+            EntryKind::HardLink(target) => archive.add_hard_link(name, target, metadata)?,
+        }
+    }
+
+    archive.finish()?;
+    Ok(())
+}
+
+// Writes the index and footer; `offset` is where the index starts.
+fn write_index<W: Write>(writer: &mut W, entries: &[EntryInfo], offset: u64) -> io::Result<()> {
+    for entry in entries {
+        writer.write_all(&(entry.path.len() as u32).to_le_bytes())?;
+        writer.write_all(entry.path.as_bytes())?;
+        entry.kind.write_to(writer)?;
+        entry.metadata.write_to(writer)?;
+        writer.write_all(&entry.offset.to_le_bytes())?;
+        writer.write_all(&entry.original_size.to_le_bytes())?;
+        writer.write_all(&entry.compressed_size.to_le_bytes())?;
+        writer.write_all(&[entry.algorithm.id()])?;
+        writer.write_all(&entry.checksum.to_le_bytes())?;
+    }
+    writer.write_all(&offset.to_le_bytes())?;
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    writer.write_all(&INDEX_MAGIC)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// Metadata of one archive entry, read without decompressing its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub path: String,
+    pub kind: EntryKind,
+    pub algorithm: Algorithm,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub checksum: u32,
+    // Byte offset of the entry's record in the archive
+    pub offset: u64,
+    pub metadata: EntryMetadata,
+}
+
+impl EntryInfo {
+    // Compressed size relative to the original, e.g. 0.25 for 4:1.
+    pub fn ratio(&self) -> f64 {
+        if self.original_size == 0 {
+            return 1.0;
+        }
+        self.compressed_size as f64 / self.original_size as f64
+    }
+}
+
+// Walks the entries of an archive in order. `next_entry` only reads the
+// entry's record and the header of its compressed stream; the data itself
+// is read through `data`, and whatever is left of it is skipped by the next
+// `next_entry` call.
+pub struct ArchiveReader<R: Read> {
+    inner: R,
+    entry_count: u64,
+    remaining: u64,
+    offset: u64,
+    // Stream header bytes already read from the current entry's data, and
+    // how many data bytes follow them
+    prefix: io::Cursor<Vec<u8>>,
+    unread: u64,
+}
+
+impl ArchiveReader<io::BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        ArchiveReader::new(io::BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 5];
+        inner.read_exact(&mut magic).map_err(|_| invalid_data("Not an archive".to_string()))?;
+        if !has_magic(&magic) {
+            return Err(invalid_data("Not an archive (missing RZAR magic bytes)".to_string()));
+        }
+        if magic[4] != ARCHIVE_VERSION {
+            return Err(invalid_data(format!("Unsupported archive version {}", magic[4])));
+        }
+        let entry_count = read_u64(&mut inner)?;
+
+        Ok(ArchiveReader {
+            inner,
+            entry_count,
+            remaining: entry_count,
+            offset: HEADER_LEN,
+            prefix: io::Cursor::new(Vec::new()),
+            unread: 0,
+        })
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    pub fn next_entry(&mut self) -> io::Result<Option<EntryInfo>> {
+        self.skip_data()?;
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+
+        let offset = self.offset;
+        let truncated = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                invalid_data(format!("Entry at offset {}: truncated record", offset))
+            }
+            _ => e,
+        };
+
+        // Read path length, path, type, metadata and checksum
+        let name_len = read_u32(&mut self.inner).map_err(truncated)? as usize;
+        let mut name_buf = Vec::new();
+        (&mut self.inner).take(name_len as u64).read_to_end(&mut name_buf)?;
+        if name_buf.len() != name_len {
+            return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let path = String::from_utf8(name_buf)
+            .map_err(|_| invalid_data(format!("Entry at offset {}: path is not valid UTF-8", offset)))?;
+        let kind = EntryKind::read_from(&mut self.inner).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => truncated(e),
+            _ => invalid_data(format!("Entry {} at offset {}: {}", path, offset, e)),
+        })?;
+        let metadata = EntryMetadata::read_from(&mut self.inner).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => truncated(e),
+            _ => invalid_data(format!("Entry {} at offset {}: {}", path, offset, e)),
+        })?;
+        let checksum = read_u32(&mut self.inner).map_err(truncated)?;
+        let original_size = read_u64(&mut self.inner).map_err(truncated)?;
+
+        // Read compressed data length and the stream header at its start
+        let data_len = read_u64(&mut self.inner).map_err(truncated)?;
+        let header_len = (format::HEADER_LEN as u64).min(data_len) as usize;
+        let mut prefix = vec![0u8; header_len];
+        self.inner.read_exact(&mut prefix).map_err(truncated)?;
+        self.unread = data_len - header_len as u64;
+        self.offset += (4 + name_len + kind.encoded_len() + metadata.encoded_len() + 4 + 8 + 8) as u64 + data_len;
+
+        let (header, _) = Header::parse(&prefix)
+            .map_err(|e| invalid_data(format!("Entry {} at offset {}: {}", path, offset, e)))?;
+        self.prefix = io::Cursor::new(prefix);
+
+        Ok(Some(EntryInfo {
+            path,
+            kind,
+            algorithm: header.algorithm,
+            original_size,
+            compressed_size: data_len,
+            checksum,
+            offset,
+            metadata,
+        }))
+    }
+
+    // Reader over the compressed stream of the entry last returned by
+    // `next_entry`.
+    pub fn data(&mut self) -> EntryData<'_, R> {
+        EntryData { archive: self }
+    }
+
+    // Reads the whole compressed stream of the current entry into memory.
+    pub fn read_data(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.data().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn skip_data(&mut self) -> io::Result<()> {
+        self.prefix = io::Cursor::new(Vec::new());
+        let skipped = io::copy(&mut (&mut self.inner).take(self.unread), &mut io::sink())?;
+        if skipped != self.unread {
+            return Err(invalid_data("Truncated entry data".to_string()));
+        }
+        self.unread = 0;
+        Ok(())
+    }
+}
+
+// Compressed data of the current entry of an `ArchiveReader`.
+pub struct EntryData<'a, R: Read> {
+    archive: &'a mut ArchiveReader<R>,
+}
+
+impl<R: Read> Read for EntryData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let archive = &mut *self.archive;
+        let n = archive.prefix.read(buf)?;
+        if n > 0 || archive.unread == 0 || buf.is_empty() {
+            return Ok(n);
+        }
+
+        let limit = buf.len().min(archive.unread.min(usize::MAX as u64) as usize);
+        let n = archive.inner.read(&mut buf[..limit])?;
+        if n == 0 {
+            return Err(invalid_data("Truncated entry data".to_string()));
+        }
+        archive.unread -= n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = io::Result<EntryInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_entry();
+        if result.is_err() {
+            // The position in the archive is unknown after an error.
+            self.remaining = 0;
+            self.unread = 0;
+        }
+        result.transpose()
+    }
+}
+
+// Lists the entries of the archive at `path` without extracting anything.
+pub fn list_archive(path: &str) -> io::Result<Vec<EntryInfo>> {
+    ArchiveReader::open(path)?.collect()
+}
+
+// Result of checking one archive entry, or the whole input when it is a
+// single compressed stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryCheck {
+    pub path: String,
+    pub size: u64,
+    pub error: Option<String>,
+}
+
+impl EntryCheck {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+// Discards everything written to it, keeping only the size and CRC32.
+#[derive(Default)]
+pub(crate) struct ChecksumSink {
+    hasher: crc32fast::Hasher,
+    size: u64,
+}
+
+impl ChecksumSink {
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn checksum(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+impl Write for ChecksumSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Decompresses every entry to a sink and checks its size and checksum. A
+// corrupt entry is reported and the check moves on to the next one; damage
+// to the archive structure itself ends the walk with a final failed check.
+pub fn verify_entries<R: Read>(mut archive: ArchiveReader<R>) -> Vec<EntryCheck> {
+    let mut checks = Vec::new();
+    loop {
+        let entry = match archive.next_entry() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(e) => {
+                // A truncated entry also breaks the walk to the next one;
+                // don't report the same damage twice.
+                if checks.last().is_none_or(EntryCheck::passed) {
+                    checks.push(EntryCheck { path: "(archive)".to_string(), size: 0, error: Some(e.to_string()) });
+                }
+                break;
+            }
+        };
+        let mut sink = ChecksumSink::default();
+        let error = copy_entry(&entry, archive.data(), &mut sink).err().map(|e| e.to_string());
+        checks.push(EntryCheck { path: entry.path, size: sink.size(), error });
+    }
+    checks
+}
+
+// What to do when an extracted entry already exists on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    // Fail with `AlreadyExists`.
+    #[default]
+    Error,
+    // Replace the existing file.
+    Overwrite,
+    // Leave the existing file alone.
+    Skip,
+    // Replace the existing file only if it is older than the entry.
+    KeepNewer,
+}
+
+// How `extract_archive` and `extract_entries` treat existing files and the
+// stored metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtractOptions {
+    pub overwrite: OverwritePolicy,
+    pub restore: RestoreOptions,
+}
+
+impl From<OverwritePolicy> for ExtractOptions {
+    fn from(overwrite: OverwritePolicy) -> Self {
+        ExtractOptions { overwrite, ..ExtractOptions::default() }
+    }
+}
+
+// Maps an archive entry name to a path under `output_dir`. Names are
+// untrusted, so anything that could point outside the directory is
+// rejected: absolute paths, drive prefixes, `.`/`..` and empty components,
+// and backslashes that Windows would treat as separators.
+pub fn entry_path(output_dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let unsafe_path = || invalid_data(format!("Unsafe path in archive: {:?}", name));
+
+    if name.is_empty() || name.starts_with('/') {
+        return Err(unsafe_path());
+    }
+    let mut path = output_dir.to_path_buf();
+    for part in name.split('/') {
+        if part.is_empty() || part == "." || part == ".." || part.contains(['\\', ':', '\0']) {
+            return Err(unsafe_path());
+        }
+        path.push(part);
+    }
+    Ok(path)
+}
+
+// Creates the parent directories of an entry one component at a time,
+// refusing to follow symlinks so an existing link inside `output_dir`
+// can't redirect the write elsewhere.
+fn create_parents(output_dir: &Path, path: &Path) -> io::Result<()> {
+    let relative = path.strip_prefix(output_dir).map_err(io::Error::other)?;
+    let mut dir = output_dir.to_path_buf();
+    let parents: Vec<_> = relative.components().collect();
+    for component in &parents[..parents.len().saturating_sub(1)] {
+        dir.push(component);
+        match std::fs::symlink_metadata(&dir) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                return Err(invalid_data(format!(
+                    "Refusing to extract through symlink {}",
+                    dir.display()
+                )));
+            }
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a directory", dir.display()),
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => std::fs::create_dir(&dir)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Decides whether the entry at `path` should be written. An existing
+// symlink is removed first so the new file never lands on the link's target.
+// An entry without a modification time counts as older than any existing
+// file.
+fn should_write(path: &Path, policy: OverwritePolicy, entry_mtime: Option<SystemTime>) -> io::Result<bool> {
+    let existing = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+
+    let write = match policy {
+        OverwritePolicy::Error => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        OverwritePolicy::Overwrite => true,
+        OverwritePolicy::Skip => false,
+        OverwritePolicy::KeepNewer => match entry_mtime {
+            Some(mtime) => existing.modified()? < mtime,
+            None => false,
+        },
+    };
+    if write && existing.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is a directory", path.display()),
+        ));
+    }
+    if write && existing.file_type().is_symlink() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(write)
+}
+
+pub fn decompress_multiple_files(input_path: &str, output_dir: &str) -> io::Result<()> {
+    extract_archive(input_path, output_dir, &ExtractOptions::default())
+}
+
+pub fn extract_archive(input_path: &str, output_dir: &str, options: &ExtractOptions) -> io::Result<()> {
+    let mut archive = ArchiveReader::open(input_path)?;
+    let mut extractor = Extractor::new(output_dir, options, false)?;
+    while let Some(entry) = archive.next_entry()? {
+        if let Some(path) = extractor.start(&entry)? {
+            extractor.write_file(&entry, &path, |out| copy_entry(&entry, archive.data(), out).map(drop))?;
+        }
+    }
+    extractor.finish()
+}
+
+// Extracts the headerless multi-file format written by the first release:
+// a file count (4 bytes), then for each file its name length (4 bytes), the
+// UTF-8 base name, the data length (4 bytes) and the data, compressed with
+// `algorithm` in its legacy format. There is no metadata to restore.
+pub fn extract_legacy_files<R: Read>(
+    mut reader: R,
+    output_dir: &Path,
+    algorithm: format::Algorithm,
+    options: &ExtractOptions,
+) -> io::Result<()> {
+    std::fs::create_dir_all(output_dir)?;
+    let count = read_u32(&mut reader)?;
+    for _ in 0..count {
+        let name_len = read_u32(&mut reader)? as usize;
+        let mut name = Vec::new();
+        (&mut reader).take(name_len as u64).read_to_end(&mut name)?;
+        if name.len() != name_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let name = String::from_utf8(name).map_err(|e| invalid_data(format!("Invalid file name: {}", e)))?;
+        // Only base names were stored
+        if name.contains('/') {
+            return Err(invalid_data(format!("Unsafe path in archive: {:?}", name)));
+        }
+        let path = entry_path(output_dir, &name)?;
+
+        let data_len = read_u32(&mut reader)? as usize;
+        let mut data = Vec::new();
+        (&mut reader).take(data_len as u64).read_to_end(&mut data)?;
+        if data.len() != data_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let data = crate::decompress_legacy(&data, algorithm)
+            .map_err(|e| invalid_data(format!("Entry {}: {}", name, e)))?;
+        if should_write(&path, options.overwrite, None)? {
+            std::fs::write(&path, data)?;
+        }
+    }
+    Ok(())
+}
+
+// Extraction state shared by the archive formats. Directories and links are
+// created by `start`; file data is written by the caller through
+// `write_file`.
+pub(crate) struct Extractor<'a> {
+    output_dir: PathBuf,
+    options: &'a ExtractOptions,
+    // Names of the file entries extracted so far, for hard links
+    files: HashSet<String>,
+    // Directories whose metadata is applied by `finish`
+    directories: Vec<(EntryInfo, PathBuf)>,
+    // Whether a hard link to a file that wasn't extracted becomes a copy,
+    // for callers whose links read as their target
+    copy_links: bool,
+}
+
+impl<'a> Extractor<'a> {
+    pub(crate) fn new(output_dir: &str, options: &'a ExtractOptions, copy_links: bool) -> io::Result<Self> {
+        // Create output directory if it doesn't exist
+        std::fs::create_dir_all(output_dir)?;
+        Ok(Extractor {
+            output_dir: PathBuf::from(output_dir),
+            options,
+            files: HashSet::new(),
+            directories: Vec::new(),
+            copy_links,
+        })
+    }
+
+    // Creates `entry` if it isn't a file. For a file, returns the path to
+    // write it to, or `None` if the overwrite policy keeps what's there.
+    pub(crate) fn start(&mut self, entry: &EntryInfo) -> io::Result<Option<PathBuf>> {
+        let output_dir = &self.output_dir;
+        let output_path = entry_path(output_dir, &entry.path)?;
+
+        // Recreate the directory structure under the output directory
+        create_parents(output_dir, &output_path)?;
+        match &entry.kind {
+            EntryKind::File => {}
+            EntryKind::HardLink(target) if self.copy_links && !self.files.contains(target) => {}
+            EntryKind::Directory => {
+                create_directory(&output_path)?;
+                self.directories.push((entry.clone(), output_path));
+                return Ok(None);
+            }
+            EntryKind::Symlink(target) => {
+                create_symlink(entry, target, &output_path, self.options)?;
+                return Ok(None);
+            }
+            EntryKind::HardLink(target) => {
+                create_hard_link(entry, target, output_dir, &output_path, self.options, &self.files)?;
+                return Ok(None);
+            }
+        }
+
+        self.files.insert(entry.path.clone());
+        let write = should_write(&output_path, self.options.overwrite, Some(entry.metadata.modified()?))?;
+        Ok(write.then_some(output_path))
+    }
+
+    // Creates the file at `path`, fills it through `write` and applies the
+    // entry's metadata.
+    pub(crate) fn write_file(
+        &self,
+        entry: &EntryInfo,
+        path: &Path,
+        write: impl FnOnce(&mut io::BufWriter<File>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut output_file = io::BufWriter::new(File::create(path)?);
+        write(&mut output_file)?;
+        output_file.into_inner().map_err(io::IntoInnerError::into_error)?;
+        restore_metadata(entry, path, &self.options.restore)
+    }
+
+    pub(crate) fn finish(self) -> io::Result<()> {
+        restore_directories(&self.directories, &self.options.restore)
+    }
+}
+
+// Applies directory metadata once everything inside is in place, deepest
+// first, so that neither writing the contents nor a read-only parent gets
+// in the way.
+fn restore_directories(directories: &[(EntryInfo, PathBuf)], options: &RestoreOptions) -> io::Result<()> {
+    for (entry, path) in directories.iter().rev() {
+        restore_metadata(entry, path, options)?;
+    }
+    Ok(())
+}
+
+fn create_directory(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a directory", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => std::fs::create_dir(path),
+        Err(e) => Err(e),
+    }
+}
+
+// Checks that a symlink target can't lead outside the output directory.
+// The parents of an extracted entry are never symlinks, so `..` is only
+// allowed at the start of the target, where it walks up through them, and
+// not past the output directory. Further down, `..` could step back out of
+// another symlink and is rejected.
+fn check_link_target(name: &str, target: &str) -> Result<(), String> {
+    if target.is_empty() || target.starts_with('/') || target.contains('\0') {
+        return Err(format!("unsafe symlink target {:?}", target));
+    }
+    let depth = name.split('/').count() - 1;
+    let mut ups = 0;
+    let mut descended = false;
+    for part in target.split('/').filter(|p| !p.is_empty() && *p != ".") {
+        if part != ".." {
+            descended = true;
+        } else if descended || ups == depth {
+            return Err(format!("unsafe symlink target {:?}", target));
+        } else {
+            ups += 1;
+        }
+    }
+    Ok(())
+}
+
+// Removes whatever `should_write` agreed to replace, since links can't be
+// created over an existing file.
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn create_symlink(entry: &EntryInfo, target: &str, path: &Path, options: &ExtractOptions) -> io::Result<()> {
+    check_link_target(&entry.path, target)
+        .map_err(|e| invalid_data(format!("Entry {} at offset {}: {}", entry.path, entry.offset, e)))?;
+    if !should_write(path, options.overwrite, Some(entry.metadata.modified()?))? {
+        return Ok(());
+    }
+    remove_existing(path)?;
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, path)?;
+        entry.metadata.restore_symlink(path, &options.restore).map_err(|e| {
+            io::Error::new(e.kind(), format!("Entry {}: can't restore metadata of {}: {}", entry.path, path.display(), e))
+        })
+    }
+    #[cfg(not(unix))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Entry {}: symlinks can't be extracted on this platform", entry.path),
+    ))
+}
+
+// Links `path` to the already extracted file `target`; `files` holds the
+// names of the file entries extracted so far.
+fn create_hard_link(
+    entry: &EntryInfo,
+    target: &str,
+    output_dir: &Path,
+    path: &Path,
+    options: &ExtractOptions,
+    files: &HashSet<String>,
+) -> io::Result<()> {
+    let source = entry_path(output_dir, target)?;
+    let is_file = std::fs::symlink_metadata(&source).is_ok_and(|meta| meta.is_file());
+    if !files.contains(target) || !is_file {
+        return Err(invalid_data(format!(
+            "Entry {} at offset {}: hard link target {} hasn't been extracted",
+            entry.path, entry.offset, target
+        )));
+    }
+    if !should_write(path, options.overwrite, Some(entry.metadata.modified()?))? {
+        return Ok(());
+    }
+    remove_existing(path)?;
+    std::fs::hard_link(&source, path)
+}
+
+fn restore_metadata(entry: &EntryInfo, path: &Path, options: &RestoreOptions) -> io::Result<()> {
+    entry.metadata.restore(path, options).map_err(|e| {
+        io::Error::new(e.kind(), format!("Entry {}: can't restore metadata of {}: {}", entry.path, path.display(), e))
+    })
+}
+
+// Decompresses an entry's data into `writer` and checks the result against
+// the entry's size and checksum. Errors name the entry and its offset.
+fn copy_entry<R: Read, W: Write>(entry: &EntryInfo, data: R, writer: W) -> io::Result<W> {
+    let located = |e: String| invalid_data(format!("Entry {} at offset {}: {}", entry.path, entry.offset, e));
+
+    let mut tee = ChecksumWriter { inner: writer, sink: ChecksumSink::default() };
+    io::copy(&mut crate::io::stream::DecompressReader::new(data), &mut tee)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => located(e.to_string()),
+            _ => e,
+        })?;
+
+    let (size, checksum) = (tee.sink.size(), tee.sink.checksum());
+    if size != entry.original_size {
+        return Err(located(format!("size mismatch, expected {} bytes, got {}", entry.original_size, size)));
+    }
+    if checksum != entry.checksum {
+        return Err(located(format!(
+            "checksum mismatch, expected {:08x}, got {:08x}",
+            entry.checksum, checksum
+        )));
+    }
+    Ok(tee.inner)
+}
+
+// Passes writes through to `inner` while checksumming them.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    sink: ChecksumSink,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.sink.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Random access to the entries of an archive through its index.
+pub struct Archive<R: Read + Seek> {
+    inner: R,
+    entries: Vec<EntryInfo>,
+    index: HashMap<String, usize>,
+}
+
+impl Archive<io::BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Archive::new(io::BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        // Checks the leading magic and version.
+        ArchiveReader::new(&mut inner)?;
+
+        let len = inner.seek(SeekFrom::End(0))?;
+        if len < FOOTER_LEN {
+            return Err(invalid_data("Archive has no index".to_string()));
+        }
+        inner.seek(SeekFrom::Start(len - FOOTER_LEN))?;
+        let index_offset = read_u64(&mut inner)?;
+        let count = read_u64(&mut inner)?;
+        let mut magic = [0u8; 4];
+        inner.read_exact(&mut magic)?;
+        if magic != INDEX_MAGIC || index_offset > len - FOOTER_LEN {
+            return Err(invalid_data("Archive index is missing or corrupt".to_string()));
+        }
+
+        inner.seek(SeekFrom::Start(index_offset))?;
+        let mut reader = (&mut inner).take(len - FOOTER_LEN - index_offset);
+        let truncated = |_| invalid_data("Archive index is truncated".to_string());
+        let mut entries = Vec::new();
+        let mut index = HashMap::new();
+        for _ in 0..count {
+            let name_len = read_u32(&mut reader).map_err(truncated)? as usize;
+            let mut name_buf = Vec::new();
+            (&mut reader).take(name_len as u64).read_to_end(&mut name_buf)?;
+            if name_buf.len() != name_len {
+                return Err(invalid_data("Archive index is truncated".to_string()));
+            }
+            let path = String::from_utf8(name_buf)
+                .map_err(|_| invalid_data("Archive index path is not valid UTF-8".to_string()))?;
+            let kind = EntryKind::read_from(&mut reader).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => truncated(e),
+                _ => invalid_data(format!("Entry {}: {}", path, e)),
+            })?;
+            let metadata = EntryMetadata::read_from(&mut reader).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => truncated(e),
+                _ => invalid_data(format!("Entry {}: {}", path, e)),
+            })?;
+            let offset = read_u64(&mut reader).map_err(truncated)?;
+            let original_size = read_u64(&mut reader).map_err(truncated)?;
+            let compressed_size = read_u64(&mut reader).map_err(truncated)?;
+            let mut id = [0u8; 1];
+            reader.read_exact(&mut id).map_err(truncated)?;
+            let algorithm = Algorithm::from_id(id[0])
+                .ok_or_else(|| invalid_data(format!("Entry {}: unknown codec id {}", path, id[0])))?;
+            let checksum = read_u32(&mut reader).map_err(truncated)?;
+
+            index.insert(path.clone(), entries.len());
+            entries.push(EntryInfo { path, kind, algorithm, original_size, compressed_size, checksum, offset, metadata });
+        }
+
+        Ok(Archive { inner, entries, index })
+    }
+
+    pub fn entries(&self) -> &[EntryInfo] {
+        &self.entries
+    }
+
+    pub fn find(&self, name: &str) -> Option<&EntryInfo> {
+        self.index.get(name).map(|&i| &self.entries[i])
+    }
+
+    // Returns a reader over the uncompressed contents of `name`; a hard link
+    // reads as the file it links to. The checksum is verified once the
+    // reader reaches the end of the entry.
+    pub fn entry(&mut self, name: &str) -> io::Result<EntryReader<'_, R>> {
+        let not_found = |name: &str| io::Error::new(io::ErrorKind::NotFound, format!("No entry named {} in archive", name));
+        let mut entry = self.find(name).ok_or_else(|| not_found(name))?.clone();
+        if let EntryKind::HardLink(target) = &entry.kind {
+            entry = self.find(target).ok_or_else(|| not_found(target))?.clone();
+        }
+
+        // The entry's own record has to agree with the index.
+        self.inner.seek(SeekFrom::Start(entry.offset))?;
+        let mut record = ArchiveReader {
+            inner: &mut self.inner,
+            entry_count: 1,
+            remaining: 1,
+            offset: entry.offset,
+            prefix: io::Cursor::new(Vec::new()),
+            unread: 0,
+        };
+        let local = record.next_entry()?.expect("one entry remaining");
+        if local != entry {
+            return Err(invalid_data(format!(
+                "Entry {} at offset {}: record doesn't match the archive index",
+                entry.path, entry.offset
+            )));
+        }
+        let prefix = std::mem::take(record.prefix.get_mut());
+        let rest = record.unread;
+
+        let data = io::Cursor::new(prefix).chain((&mut self.inner).take(rest));
+        Ok(EntryReader {
+            decoder: crate::io::stream::DecompressReader::new(data),
+            sink: ChecksumSink::default(),
+            entry,
+        })
+    }
+}
+
+// Decompressing reader returned by `Archive::entry`.
+pub struct EntryReader<'a, R: Read> {
+    decoder: crate::io::stream::DecompressReader<io::Chain<io::Cursor<Vec<u8>>, io::Take<&'a mut R>>>,
+    sink: ChecksumSink,
+    entry: EntryInfo,
+}
+
+impl<R: Read> EntryReader<'_, R> {
+    pub fn info(&self) -> &EntryInfo {
+        &self.entry
+    }
+}
+
+impl<R: Read> Read for EntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let entry = &self.entry;
+        let n = self
+            .decoder
+            .read(buf)
+            .map_err(|e| invalid_data(format!("Entry {} at offset {}: {}", entry.path, entry.offset, e)))?;
+        self.sink.write_all(&buf[..n])?;
+        if n == 0 && !buf.is_empty() && self.sink.checksum() != entry.checksum {
+            return Err(invalid_data(format!(
+                "Entry {} at offset {}: checksum mismatch, expected {:08x}, got {:08x}",
+                entry.path, entry.offset, entry.checksum, self.sink.checksum()
+            )));
+        }
+        Ok(n)
+    }
+}
+
+// Extracts the named entries under `output_dir`, seeking to each through
+// the archive index. A hard link whose target isn't among the names is
+// extracted as a copy of the target.
+pub fn extract_entries(input_path: &str, names: &[String], output_dir: &str, options: &ExtractOptions) -> io::Result<()> {
+    let mut archive = Archive::open(input_path)?;
+    let mut extractor = Extractor::new(output_dir, options, true)?;
+    for name in names {
+        let entry = archive
+            .find(name)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No entry named {} in archive", name)))?;
+        if let Some(path) = extractor.start(&entry)? {
+            let mut reader = archive.entry(name)?;
+            extractor.write_file(&entry, &path, |out| io::copy(&mut reader, out).map(drop))?;
+        }
+    }
+    extractor.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn path_str(path: &Path) -> String {
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_archive_path_normalization() {
+        assert_eq!(archive_path(Path::new("a/b/c.txt")).unwrap(), "a/b/c.txt");
+        assert_eq!(archive_path(Path::new("./a/./b.txt")).unwrap(), "a/b.txt");
+        assert_eq!(archive_path(Path::new("/abs/x.txt")).unwrap(), "abs/x.txt");
+        assert_eq!(archive_path(Path::new("../x.txt")).unwrap(), "x.txt");
+    }
+
+    #[test]
+    fn test_directory_round_trip() {
+        let src = tempfile::tempdir().unwrap();
+        let root = src.path().join("project");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b/deep")).unwrap();
+        fs::write(root.join("a/x.txt"), b"from a").unwrap();
+        fs::write(root.join("b/x.txt"), b"from b").unwrap();
+        fs::write(root.join("b/deep/y.log"), b"deep file ".repeat(100)).unwrap();
+
+        let archive = src.path().join("out.rz");
+        let inputs = vec![path_str(&root)];
+        compress_multiple_files(&inputs, &path_str(&archive), Algorithm::Lz77, 6).unwrap();
+        assert!(is_archive(&archive).unwrap());
+
+        let dest = tempfile::tempdir().unwrap();
+        decompress_multiple_files(&path_str(&archive), &path_str(dest.path())).unwrap();
+
+        let extracted = dest.path().join(archive_path(&root).unwrap());
+        assert_eq!(fs::read(extracted.join("a/x.txt")).unwrap(), b"from a");
+        assert_eq!(fs::read(extracted.join("b/x.txt")).unwrap(), b"from b");
+        assert_eq!(fs::read(extracted.join("b/deep/y.log")).unwrap(), b"deep file ".repeat(100));
+    }
+
+    #[test]
+    fn test_same_file_name_in_different_directories() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("a")).unwrap();
+        fs::create_dir_all(src.path().join("b")).unwrap();
+        fs::write(src.path().join("a/x.txt"), b"one").unwrap();
+        fs::write(src.path().join("b/x.txt"), b"two").unwrap();
+
+        let inputs = vec![path_str(&src.path().join("a/x.txt")), path_str(&src.path().join("b/x.txt"))];
+        let entries = collect_entries(&inputs).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_ne!(entries[0].name, entries[1].name);
+        assert!(entries[0].name.ends_with("a/x.txt"));
+    }
+
+    #[test]
+    fn test_duplicate_entries_rejected() {
+        let src = tempfile::tempdir().unwrap();
+        let file = src.path().join("x.txt");
+        fs::write(&file, b"x").unwrap();
+        let inputs = vec![path_str(&file), path_str(&file)];
+        assert!(collect_entries(&inputs).is_err());
+    }
+
+    #[test]
+    fn test_rejects_non_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("plain.rz");
+        fs::write(&file, crate::compress(b"not an archive", Algorithm::Rle).unwrap()).unwrap();
+        assert!(!is_archive(&file).unwrap());
+        let out = dir.path().join("out");
+        assert!(decompress_multiple_files(&path_str(&file), &path_str(&out)).is_err());
+    }
+
+    // Builds an archive in memory. The writer doesn't check names, so tests
+    // can store names that `collect_entries` would never produce.
+    fn raw_archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(io::Cursor::new(Vec::new()), Algorithm::Lz77, 6).unwrap();
+        for (name, data) in entries {
+            writer.add(name, &test_metadata(), *data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // A plain file modified now, owned by the current user.
+    fn test_metadata() -> EntryMetadata {
+        let mtime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+        let owner = EntryMetadata::from_fs(&fs::metadata(std::env::temp_dir()).unwrap());
+        EntryMetadata { mode: 0o100644, mtime, uid: owner.uid, gid: owner.gid, ..EntryMetadata::default() }
+    }
+
+    fn list_entries(archive: &[u8]) -> Vec<EntryInfo> {
+        ArchiveReader::new(archive).unwrap().collect::<io::Result<_>>().unwrap()
+    }
+
+    fn index_offset(archive: &[u8]) -> usize {
+        let footer = archive.len() - FOOTER_LEN as usize;
+        u64::from_le_bytes(archive[footer..footer + 8].try_into().unwrap()) as usize
+    }
+
+    // Two files as written by the first release's multi-file mode with LZ77
+    const BASELINE_FILES: &[u8] = &[
+        2, 0, 0, 0, // file count
+        5, 0, 0, 0, b'a', b'.', b't', b'x', b't', // name
+        7, 0, 0, 0, 0, b'a', 0, b'b', 1, 2, 4, // "ab", then 4 bytes from offset 2
+        5, 0, 0, 0, b'b', b'.', b't', b'x', b't',
+        2, 0, 0, 0, 0, b'z',
+    ];
+
+    #[test]
+    fn test_extract_legacy_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let options = ExtractOptions::default();
+        extract_legacy_files(BASELINE_FILES, &out, format::Algorithm::Lz77, &options).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"ababab");
+        assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"z");
+
+        let err = extract_legacy_files(BASELINE_FILES, &out, format::Algorithm::Lz77, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        // The entries have no modification time, so existing files count
+        // as newer, however old they are
+        fs::write(out.join("a.txt"), b"old").unwrap();
+        let epoch = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1);
+        File::options().write(true).open(out.join("a.txt")).unwrap().set_modified(epoch).unwrap();
+        extract_legacy_files(BASELINE_FILES, &out, format::Algorithm::Lz77, &OverwritePolicy::KeepNewer.into()).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+        extract_legacy_files(BASELINE_FILES, &out, format::Algorithm::Lz77, &OverwritePolicy::Overwrite.into()).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"ababab");
+        let truncated = &BASELINE_FILES[..BASELINE_FILES.len() - 1];
+        let err = extract_legacy_files(truncated, &dir.path().join("t"), format::Algorithm::Lz77, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut evil = vec![1, 0, 0, 0, 5, 0, 0, 0];
+        evil.extend_from_slice(b"../ev");
+        evil.extend_from_slice(&[2, 0, 0, 0, 0, b'x']);
+        let err = extract_legacy_files(&evil[..], &out, format::Algorithm::Lz77, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!dir.path().join("ev").exists());
+    }
+
+    #[test]
+    fn test_entry_path_rejects_escapes() {
+        let base = Path::new("out");
+        for name in ["../x", "a/../../x", "/etc/passwd", "a//b", "./x", "", "a/", "..\\x", "C:x", "a/\0"] {
+            assert!(entry_path(base, name).is_err(), "accepted {:?}", name);
+        }
+        assert_eq!(entry_path(base, "a/b.txt").unwrap(), base.join("a").join("b.txt"));
+        assert_eq!(entry_path(base, "..a/b..").unwrap(), base.join("..a").join("b.."));
+    }
+
+    #[test]
+    fn test_malicious_archive_stays_inside_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        for name in ["../escaped.txt", "nested/../../escaped.txt", "/tmp/escaped.txt"] {
+            let archive = dir.path().join("evil.rz");
+            fs::write(&archive, raw_archive(&[(name, b"pwned")])).unwrap();
+            let err = decompress_multiple_files(&path_str(&archive), &path_str(&out)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(!dir.path().join("escaped.txt").exists());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escape_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        let out = dir.path().join("out");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&out).unwrap();
+        std::os::unix::fs::symlink(&outside, out.join("link")).unwrap();
+        std::os::unix::fs::symlink(outside.join("target.txt"), out.join("file.txt")).unwrap();
+
+        let archive = dir.path().join("evil.rz");
+        fs::write(&archive, raw_archive(&[("link/x.txt", b"pwned")])).unwrap();
+        assert!(decompress_multiple_files(&path_str(&archive), &path_str(&out)).is_err());
+        assert!(!outside.join("x.txt").exists());
+
+        // Overwriting a symlinked file replaces the link, not its target.
+        fs::write(&archive, raw_archive(&[("file.txt", b"data")])).unwrap();
+        extract_archive(&path_str(&archive), &path_str(&out), &OverwritePolicy::Overwrite.into()).unwrap();
+        assert!(!outside.join("target.txt").exists());
+        assert_eq!(fs::read(out.join("file.txt")).unwrap(), b"data");
+        assert!(!fs::symlink_metadata(out.join("file.txt")).unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn test_overwrite_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("a.txt"), b"old").unwrap();
+
+        let archive = dir.path().join("new.rz");
+        fs::write(&archive, raw_archive(&[("a.txt", b"new"), ("b.txt", b"b")])).unwrap();
+        let (archive, out_str) = (path_str(&archive), path_str(&out));
+
+        let err = extract_archive(&archive, &out_str, &OverwritePolicy::Error.into()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+
+        extract_archive(&archive, &out_str, &OverwritePolicy::Skip.into()).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+        assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"b");
+
+        // The existing file is newer than the entry, so it's kept.
+        let later = SystemTime::now() + std::time::Duration::from_secs(3600);
+        File::options().write(true).open(out.join("a.txt")).unwrap().set_modified(later).unwrap();
+        extract_archive(&archive, &out_str, &OverwritePolicy::KeepNewer.into()).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+
+        let earlier = SystemTime::now() - std::time::Duration::from_secs(3600);
+        File::options().write(true).open(out.join("a.txt")).unwrap().set_modified(earlier).unwrap();
+        extract_archive(&archive, &out_str, &OverwritePolicy::KeepNewer.into()).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"new");
+
+        fs::write(out.join("a.txt"), b"old").unwrap();
+        extract_archive(&archive, &out_str, &OverwritePolicy::Overwrite.into()).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn test_corrupt_entry_names_entry_and_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bytes = raw_archive(&[("first.txt", b"first"), ("second.txt", b"second entry")]);
+        let second_offset = ArchiveReader::new(&bytes[..]).unwrap().nth(1).unwrap().unwrap().offset as usize;
+
+        // Corrupt the stored checksum of the second entry.
+        let checksum_pos = second_offset + 4 + "second.txt".len() + 1 + test_metadata().encoded_len();
+        bytes[checksum_pos] ^= 0xff;
+        let archive = dir.path().join("bad.rz");
+        fs::write(&archive, &bytes).unwrap();
+
+        let out = dir.path().join("out");
+        let err = decompress_multiple_files(&path_str(&archive), &path_str(&out)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let msg = err.to_string();
+        assert!(msg.contains("second.txt"), "{}", msg);
+        assert!(msg.contains(&format!("offset {}", second_offset)), "{}", msg);
+        assert!(msg.contains("checksum mismatch"), "{}", msg);
+    }
+
+    #[test]
+    fn test_compressed_formats_are_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("media.rz");
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&b"looks compressible but isn't tried ".repeat(100));
+        let text = b"compressible ".repeat(500);
+        fs::write(&archive, raw_archive(&[("logo.png", &png), ("notes.txt", &text)])).unwrap();
+
+        let entries = list_archive(&path_str(&archive)).unwrap();
+        assert_eq!(entries[0].algorithm, Algorithm::Stored);
+        assert!(entries[0].compressed_size > png.len() as u64);
+        assert_eq!(entries[1].algorithm, Algorithm::Lz77);
+
+        let out = dir.path().join("out");
+        decompress_multiple_files(&path_str(&archive), &path_str(&out)).unwrap();
+        assert_eq!(fs::read(out.join("logo.png")).unwrap(), png);
+        assert_eq!(fs::read(out.join("notes.txt")).unwrap(), text);
+    }
+
+    #[test]
+    fn test_list_without_extracting() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("list.rz");
+        let big = b"compressible ".repeat(500);
+        fs::write(&archive, raw_archive(&[("docs/a.txt", &big), ("b.bin", b"xyz")])).unwrap();
+
+        let entries = list_archive(&path_str(&archive)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "docs/a.txt");
+        assert_eq!(entries[0].original_size, big.len() as u64);
+        assert_eq!(entries[0].algorithm, Algorithm::Lz77);
+        assert!(entries[0].ratio() < 0.1);
+        assert_eq!(entries[1].path, "b.bin");
+        assert_eq!(entries[1].original_size, 3);
+        assert_eq!(entries[1].checksum, crc32fast::hash(b"xyz"));
+    }
+
+    #[test]
+    fn test_reader_read_data_and_skip() {
+        let bytes = raw_archive(&[("one", b"first entry"), ("two", b"second entry")]);
+        let mut reader = ArchiveReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.entry_count(), 2);
+
+        // Skip the first entry's data entirely.
+        assert_eq!(reader.next_entry().unwrap().unwrap().path, "one");
+        let second = reader.next_entry().unwrap().unwrap();
+        assert_eq!(second.path, "two");
+        let data = reader.read_data().unwrap();
+        assert_eq!(data.len() as u64, second.compressed_size);
+        assert_eq!(copy_entry(&second, &data[..], Vec::new()).unwrap(), b"second entry");
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reader_truncated_archive() {
+        let bytes = raw_archive(&[("one", b"first entry"), ("two", b"second entry")]);
+        let reader = ArchiveReader::new(&bytes[..index_offset(&bytes) - 5]).unwrap();
+        let results: Vec<_> = reader.collect();
+        assert!(results[0].is_ok());
+        assert!(results.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_reader_huge_name_length() {
+        // A corrupt name length must not be allocated up front
+        let mut bytes = raw_archive(&[("one", b"first entry")])[..13].to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(b"one");
+        let err = ArchiveReader::new(&bytes[..]).unwrap().next_entry().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("truncated record"), "{}", err);
+    }
+
+    #[test]
+    fn test_verify_reports_each_entry() {
+        let mut bytes = raw_archive(&[("good.txt", b"good data"), ("bad.txt", b"bad data"), ("last.txt", b"last")]);
+        let bad_checksum = bytes.windows(7).position(|w| w == b"bad.txt").unwrap() + 7 + 1 + test_metadata().encoded_len();
+        bytes[bad_checksum] ^= 0xff;
+
+        let checks = verify_entries(ArchiveReader::new(&bytes[..]).unwrap());
+        let passed: Vec<_> = checks.iter().map(|c| (c.path.as_str(), c.passed())).collect();
+        assert_eq!(passed, [("good.txt", true), ("bad.txt", false), ("last.txt", true)]);
+        assert_eq!(checks[0].size, 9);
+        assert!(checks[1].error.as_ref().unwrap().contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn test_verify_stops_at_truncation() {
+        let bytes = raw_archive(&[("one", b"first entry"), ("two", b"second entry")]);
+        let checks = verify_entries(ArchiveReader::new(&bytes[..index_offset(&bytes) - 3]).unwrap());
+        assert_eq!(checks.len(), 2);
+        assert!(checks[0].passed());
+        assert!(!checks[1].passed());
+    }
+
+    #[test]
+    fn test_random_access_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("tree");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        for i in 0..20 {
+            fs::write(root.join(format!("f{:02}.txt", i)), format!("file number {}", i).repeat(50)).unwrap();
+        }
+        fs::write(root.join("sub/wanted.txt"), b"the one we want").unwrap();
+
+        let archive_file = dir.path().join("tree.rz");
+        compress_multiple_files(&[path_str(&root)], &path_str(&archive_file), Algorithm::Lzh, 6).unwrap();
+
+        let mut archive = Archive::open(&archive_file).unwrap();
+        // 21 files and two directories
+        assert_eq!(archive.entries().len(), 23);
+        let wanted = format!("{}/sub/wanted.txt", archive_path(&root).unwrap());
+        let mut output = Vec::new();
+        archive.entry(&wanted).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"the one we want");
+
+        // The index agrees with a sequential walk.
+        let walked = list_archive(&path_str(&archive_file)).unwrap();
+        assert_eq!(walked, archive.entries());
+
+        let err = archive.entry("missing.txt").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_extract_single_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("a.rz");
+        fs::write(&archive, raw_archive(&[("a/one.txt", b"one"), ("b/two.txt", b"two")])).unwrap();
+
+        let out = dir.path().join("out");
+        let names = vec!["b/two.txt".to_string()];
+        extract_entries(&path_str(&archive), &names, &path_str(&out), &ExtractOptions::default()).unwrap();
+        assert_eq!(fs::read(out.join("b/two.txt")).unwrap(), b"two");
+        assert!(!out.join("a").exists());
+    }
+
+    #[test]
+    fn test_entry_reader_detects_corruption() {
+        let mut bytes = raw_archive(&[("x.txt", b"some data to corrupt")]);
+        let entry = list_entries(&bytes).remove(0);
+        let last = entry.offset + (4 + 5 + 1 + entry.metadata.encoded_len() + 4 + 8 + 8) as u64 + entry.compressed_size - 1;
+        bytes[last as usize] ^= 0x20;
+
+        let mut archive = Archive::new(io::Cursor::new(bytes)).unwrap();
+        let mut output = Vec::new();
+        assert!(archive.entry("x.txt").unwrap().read_to_end(&mut output).is_err());
+    }
+
+    #[test]
+    fn test_archive_without_index_rejected() {
+        let bytes = raw_archive(&[("x.txt", b"x")]);
+        let without_footer = bytes[..bytes.len() - 4].to_vec();
+        assert!(Archive::new(io::Cursor::new(without_footer)).is_err());
+    }
+
+    #[test]
+    fn test_streams_multi_block_entries() {
+        let big: Vec<u8> = (0..3 * crate::io::stream::DEFAULT_BLOCK_SIZE as u32 + 123)
+            .map(|i| (i / 7 % 251) as u8)
+            .collect();
+        let bytes = raw_archive(&[("big.bin", &big), ("small.txt", b"small")]);
+
+        let entries = list_entries(&bytes);
+        assert_eq!(entries[0].original_size, big.len() as u64);
+        assert!(entries[0].compressed_size < big.len() as u64 / 10);
+
+        let mut archive = Archive::new(io::Cursor::new(bytes)).unwrap();
+        let mut output = Vec::new();
+        archive.entry("big.bin").unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, big);
+    }
+
+    #[test]
+    fn test_sizes_beyond_u32() {
+        // A record claiming more than 4 GiB of data; the reader has to keep
+        // the full 64-bit values even though the data itself is missing.
+        let stream = crate::compress_stream(&b"tiny"[..], Vec::new(), Algorithm::Rle, 6).unwrap();
+        let huge = 5u64 << 30;
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(ARCHIVE_VERSION);
+        bytes.extend_from_slice(&1u64.to_le_bytes());
+        bytes.extend_from_slice(&(7u32).to_le_bytes());
+        bytes.extend_from_slice(b"big.img");
+        EntryKind::File.write_to(&mut bytes).unwrap();
+        EntryMetadata::default().write_to(&mut bytes).unwrap();
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(huge + 1).to_le_bytes());
+        bytes.extend_from_slice(&huge.to_le_bytes());
+        bytes.extend_from_slice(&stream);
+
+        let mut reader = ArchiveReader::new(&bytes[..]).unwrap();
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.original_size, huge + 1);
+        assert_eq!(entry.compressed_size, huge);
+        assert!(reader.next_entry().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_metadata_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("meta");
+        fs::create_dir_all(&root).unwrap();
+        let script = root.join("run.sh");
+        fs::write(&script, b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::new(1_600_000_000, 123_456_789);
+        File::options().write(true).open(&script).unwrap().set_modified(mtime).unwrap();
+
+        let archive = dir.path().join("meta.rz");
+        compress_multiple_files(&[path_str(&root)], &path_str(&archive), Algorithm::Lz77, 6).unwrap();
+        let entries = list_archive(&path_str(&archive)).unwrap();
+        let entry = entries.iter().find(|e| e.path.ends_with("run.sh")).unwrap();
+        assert_eq!(entry.metadata.mode & 0o7777, 0o750);
+        assert_eq!(entry.metadata.modified().unwrap(), mtime);
+        assert_eq!(entries, Archive::open(&archive).unwrap().entries());
+
+        let out = dir.path().join("out");
+        let options = ExtractOptions {
+            restore: RestoreOptions { same_owner: false, preserve_permissions: true },
+            ..ExtractOptions::default()
+        };
+        extract_archive(&path_str(&archive), &path_str(&out), &options).unwrap();
+        let extracted = fs::metadata(out.join(&entry.path)).unwrap();
+        assert_eq!(extracted.permissions().mode() & 0o7777, 0o750);
+        assert_eq!(extracted.modified().unwrap(), mtime);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_links_and_empty_directories_round_trip() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("tree");
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("data.txt"), b"shared contents").unwrap();
+        fs::hard_link(root.join("data.txt"), root.join("sub/again.txt")).unwrap();
+        std::os::unix::fs::symlink("../data.txt", root.join("sub/link")).unwrap();
+
+        let archive = dir.path().join("tree.rz");
+        compress_multiple_files(&[path_str(&root)], &path_str(&archive), Algorithm::Lz77, 6).unwrap();
+        let prefix = archive_path(&root).unwrap();
+        let kinds: Vec<_> = list_archive(&path_str(&archive))
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.path[prefix.len()..].to_string(), e.kind))
+            .collect();
+        assert_eq!(kinds, [
+            (String::new(), EntryKind::Directory),
+            ("/data.txt".to_string(), EntryKind::File),
+            ("/empty".to_string(), EntryKind::Directory),
+            ("/sub".to_string(), EntryKind::Directory),
+            ("/sub/again.txt".to_string(), EntryKind::HardLink(format!("{}/data.txt", prefix))),
+            ("/sub/link".to_string(), EntryKind::Symlink("../data.txt".to_string())),
+        ]);
+
+        let out = dir.path().join("out");
+        decompress_multiple_files(&path_str(&archive), &path_str(&out)).unwrap();
+        let tree = out.join(&prefix);
+        assert!(fs::read_dir(tree.join("empty")).unwrap().next().is_none());
+        assert_eq!(fs::read_link(tree.join("sub/link")).unwrap(), Path::new("../data.txt"));
+        assert_eq!(fs::read(tree.join("sub/link")).unwrap(), b"shared contents");
+        let (a, b) = (fs::metadata(tree.join("data.txt")).unwrap(), fs::metadata(tree.join("sub/again.txt")).unwrap());
+        assert_eq!(a.ino(), b.ino());
+
+        // Extracted on its own, a hard link gets a copy of its target.
+        let single = dir.path().join("single");
+        let names = vec![format!("{}/sub/again.txt", prefix)];
+        extract_entries(&path_str(&archive), &names, &path_str(&single), &ExtractOptions::default()).unwrap();
+        assert_eq!(fs::read(single.join(&names[0])).unwrap(), b"shared contents");
+        let mut archive = Archive::open(&archive).unwrap();
+        let mut output = Vec::new();
+        archive.entry(&names[0]).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"shared contents");
+    }
+
+    #[test]
+    fn test_link_targets_checked() {
+        for (name, target) in [("a", "x"), ("a/b", "../x"), ("a/b/c", "../../x/y"), ("a", "./x/./y")] {
+            assert!(check_link_target(name, target).is_ok(), "rejected {} -> {}", name, target);
+        }
+        for (name, target) in [("a", "../x"), ("a/b", "../../x"), ("a", "/etc/passwd"), ("a", "x/../../y"), ("a", "")] {
+            assert!(check_link_target(name, target).is_err(), "accepted {} -> {}", name, target);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unsafe_links_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let archive = dir.path().join("evil.rz");
+
+        let mut writer = ArchiveWriter::new(io::Cursor::new(Vec::new()), Algorithm::Lz77, 6).unwrap();
+        writer.add_symlink("escape", "../outside", &test_metadata()).unwrap();
+        fs::write(&archive, writer.finish().unwrap().into_inner()).unwrap();
+        let err = decompress_multiple_files(&path_str(&archive), &path_str(&out)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(fs::symlink_metadata(out.join("escape")).is_err());
+
+        // A hard link has to point at a file extracted earlier.
+        let mut writer = ArchiveWriter::new(io::Cursor::new(Vec::new()), Algorithm::Lz77, 6).unwrap();
+        assert!(writer.add_hard_link("link", "missing", &test_metadata()).is_err());
+        writer.add_directory("dir", &test_metadata()).unwrap();
+        assert!(writer.add_hard_link("link", "dir", &test_metadata()).is_err());
+        let mut writer = ArchiveWriter::new(io::Cursor::new(Vec::new()), Algorithm::Lz77, 6).unwrap();
+        writer.add("first.txt", &test_metadata(), &b"first"[..]).unwrap();
+        writer.add_hard_link("link", "first.txt", &test_metadata()).unwrap();
+        let mut bytes = writer.finish().unwrap().into_inner();
+        // Point the link's record at a name outside the output directory.
+        let at = bytes.windows(9).enumerate().filter(|(_, w)| w == b"first.txt").nth(1).unwrap().0;
+        bytes[at..at + 9].copy_from_slice(b"../../etc");
+        fs::write(&archive, &bytes).unwrap();
+        let err = extract_archive(&path_str(&archive), &path_str(&out), &OverwritePolicy::Overwrite.into()).unwrap_err();
+        assert!(err.to_string().contains("../../etc"), "{}", err);
+        assert!(fs::symlink_metadata(out.join("link")).is_err());
+    }
+}
@@ -1,4 +1,7 @@
-use clap::{Parser, ValueEnum};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::Parser;
+
+use crate::Algorithm;
 
 #[derive(Parser)]
 pub struct CliArgs {
@@ -6,15 +9,17 @@ pub struct CliArgs {
     pub input: String,
     pub output: String,
 
-    #[clap(long, value_enum)]
-    pub algo: Option<Algo>,
+    #[clap(long, value_parser = algorithm_parser())]
+    pub algo: Option<Algorithm>,
 }
 
-#[derive(ValueEnum, Clone)]
-pub enum Algo {
-    Rle,
-    Lz77,
+// Accepts the name of any registered codec, so codecs added with
+// `codec::register` before parsing show up in `--algo` too.
+pub fn algorithm_parser() -> impl TypedValueParser<Value = Algorithm> {
+    let names: Vec<&'static str> = crate::codec::codecs().iter().map(|codec| codec.name()).collect();
+    PossibleValuesParser::new(names).map(|name| Algorithm::from_name(&name).expect("name is registered"))
 }
+
 impl CliArgs {
     pub fn parse_args() -> Result<Self, String> {
         let args = Self::parse();
@@ -24,13 +29,19 @@ impl CliArgs {
             return Err("Command must be either 'compress' or 'decompress'".to_string());
         }
 
-        // If no algorithm specified, detect based on file extension
+        // If no algorithm specified, detect from the content when
+        // compressing, falling back to the file extension
         let args = if args.algo.is_none() {
-            let detected_algo = match crate::detect::detect_best_algorithm(&args.input) {
-                "rle" => Algo::Rle,
-                "lz" => Algo::Lz77,
-                _ => Algo::Lz77 // Default to LZ77
+            let detected = if args.command == "compress" {
+                crate::detect::detect_file(std::path::Path::new(&args.input))
+                    .ok()
+                    .map(|detection| detection.algorithm)
+            } else {
+                None
             };
+            let detected_algo = detected
+                .or_else(|| crate::detect::extension_hint(&args.input))
+                .unwrap_or(Algorithm::Lz77);
             Self {
                 algo: Some(detected_algo),
                 ..args
@@ -51,11 +62,11 @@ impl CliArgs {
     }
 
     pub fn is_rle(&self) -> bool {
-        matches!(self.algo, Some(Algo::Rle))
+        self.algo == Some(Algorithm::Rle)
     }
 
     pub fn is_lz77(&self) -> bool {
-        matches!(self.algo, Some(Algo::Lz77))
+        self.algo == Some(Algorithm::Lz77)
     }
 }
 
@@ -0,0 +1,511 @@
+// Codecs are looked up by id (stream headers, archive indexes) or by name
+// (the command line) in a process-wide registry. The built-in algorithms
+// are registered up front; other crates can add their own with `register`
+// and then use them through `Algorithm::Custom`.
+
+use std::io::{Read, Write};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+use crate::algorithms;
+use crate::format::Algorithm;
+use crate::io::stream::{CompressWriter, DecompressReader, Encoder};
+
+// Ids below this are reserved for codecs built into this crate.
+pub const MIN_CUSTOM_ID: u8 = 128;
+
+// How many leading bytes `Codec::is_stream` gets to look at.
+pub const SNIFF_LEN: usize = 16;
+
+pub trait Codec: Send + Sync {
+    // Recorded in stream headers and archive indexes, so it must never
+    // change once data has been written with it.
+    fn id(&self) -> u8;
+    // Accepted by `--algo` and shown in listings.
+    fn name(&self) -> &'static str;
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, String>;
+    // `limit` is the most output the caller accepts, usually the size
+    // recorded in the header or block. Codecs that can tell early should fail
+    // rather than produce or allocate more; `crate::decode` checks the rest.
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, String>;
+
+    // Whether `--algo auto` includes this codec in its trials.
+    fn in_trials(&self) -> bool {
+        true
+    }
+
+    // Codecs with a stream format of their own (gzip, zlib) override the
+    // methods below. Everything else is streamed through the container, one
+    // `compress` call per block.
+    fn is_standard_stream(&self) -> bool {
+        false
+    }
+
+    // Recognizes this codec's standard stream from its first SNIFF_LEN bytes.
+    fn is_stream(&self, _prefix: &[u8]) -> bool {
+        false
+    }
+
+    fn stream_encoder<'a>(&self, inner: Box<dyn Write + 'a>, level: u32) -> Box<dyn Encoder + 'a> {
+        let algorithm = Algorithm::from_id(self.id()).expect("codec must be registered before use");
+        Box::new(CompressWriter::new(inner, algorithm, level))
+    }
+
+    fn stream_decoder<'a>(&self, inner: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(DecompressReader::new(inner))
+    }
+}
+
+type Registry = RwLock<Vec<Arc<dyn Codec>>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    // Registration order is trial order, and trial ties go to the earlier
+    // codec.
+    REGISTRY.get_or_init(|| {
+        RwLock::new(vec![
+            Arc::new(Lz77),
+            Arc::new(Lzh),
+            Arc::new(Deflate),
+            Arc::new(PackBits),
+            Arc::new(Rle),
+            Arc::new(Zlib),
+            Arc::new(Gzip),
+            Arc::new(Stored),
+            Arc::new(Auto),
+        ])
+    })
+}
+
+// Adds a codec for this process. Its id must be MIN_CUSTOM_ID or above, and
+// neither the id nor the name may already be taken.
+pub fn register<C: Codec + 'static>(codec: C) -> Result<(), String> {
+    if codec.id() < MIN_CUSTOM_ID {
+        return Err(format!(
+            "Codec id {} is reserved; custom codecs need an id of {} or more",
+            codec.id(),
+            MIN_CUSTOM_ID
+        ));
+    }
+    if codec.name().is_empty() {
+        return Err("Codec name can't be empty".to_string());
+    }
+
+    let mut codecs = registry().write().unwrap_or_else(PoisonError::into_inner);
+    if let Some(existing) = codecs.iter().find(|c| c.id() == codec.id() || c.name() == codec.name()) {
+        return Err(format!(
+            "Codec {} (id {}) clashes with registered codec {} (id {})",
+            codec.name(),
+            codec.id(),
+            existing.name(),
+            existing.id()
+        ));
+    }
+    codecs.push(Arc::new(codec));
+    Ok(())
+}
+
+// Removes a codec added by `register` and returns it. Built-in codecs stay.
+pub fn unregister(id: u8) -> Option<Arc<dyn Codec>> {
+    if id < MIN_CUSTOM_ID {
+        return None;
+    }
+    let mut codecs = registry().write().unwrap_or_else(PoisonError::into_inner);
+    let index = codecs.iter().position(|c| c.id() == id)?;
+    Some(codecs.remove(index))
+}
+
+// Every registered codec, in registration order.
+pub fn codecs() -> Vec<Arc<dyn Codec>> {
+    registry().read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+pub fn by_id(id: u8) -> Option<Arc<dyn Codec>> {
+    codecs().into_iter().find(|c| c.id() == id)
+}
+
+pub fn by_name(name: &str) -> Option<Arc<dyn Codec>> {
+    codecs().into_iter().find(|c| c.name() == name)
+}
+
+pub fn lookup(algorithm: Algorithm) -> Result<Arc<dyn Codec>, String> {
+    by_id(algorithm.id()).ok_or_else(|| format!("Unknown codec id {}", algorithm.id()))
+}
+
+// The codecs `--algo auto` tries, in trial order.
+pub fn trial_candidates() -> Vec<Arc<dyn Codec>> {
+    codecs().into_iter().filter(|c| c.in_trials()).collect()
+}
+
+// Finds the codec whose standard stream starts with `prefix`.
+pub fn sniff(prefix: &[u8]) -> Option<Arc<dyn Codec>> {
+    codecs().into_iter().find(|c| c.is_stream(prefix))
+}
+
+struct Rle;
+
+impl Codec for Rle {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn name(&self) -> &'static str {
+        "rle"
+    }
+
+    fn compress(&self, data: &[u8], _level: u32) -> Result<Vec<u8>, String> {
+        algorithms::rle::compress_rle(data)
+    }
+
+    fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, String> {
+        algorithms::rle::decompress_rle(data)
+    }
+}
+
+struct Lz77;
+
+impl Codec for Lz77 {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn name(&self) -> &'static str {
+        "lz77"
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+        algorithms::lz77::compress_lz77_with_level(data, level)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+        algorithms::lz77::decompress_lz77(data, limit)
+    }
+}
+
+struct Lzh;
+
+impl Codec for Lzh {
+    fn id(&self) -> u8 {
+        3
+    }
+
+    fn name(&self) -> &'static str {
+        "lzh"
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+        algorithms::lzh::compress_lzh_with_level(data, level)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+        algorithms::lzh::decompress_lzh(data, limit)
+    }
+}
+
+struct PackBits;
+
+impl Codec for PackBits {
+    fn id(&self) -> u8 {
+        4
+    }
+
+    fn name(&self) -> &'static str {
+        "packbits"
+    }
+
+    fn compress(&self, data: &[u8], _level: u32) -> Result<Vec<u8>, String> {
+        algorithms::packbits::compress_packbits(data)
+    }
+
+    fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, String> {
+        algorithms::packbits::decompress_packbits(data)
+    }
+}
+
+struct Deflate;
+
+impl Codec for Deflate {
+    fn id(&self) -> u8 {
+        5
+    }
+
+    fn name(&self) -> &'static str {
+        "deflate"
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+        algorithms::deflate::compress_deflate(data, level)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+        algorithms::deflate::decompress_deflate(data, limit)
+    }
+}
+
+// Zlib and gzip carry their own header and checksum, so single streams are
+// written in the standard format rather than inside the container. Archive
+// entries still use the container, with one zlib or gzip stream per block.
+// Both are deflate with a wrapper, so they would never win a trial against
+// plain deflate.
+struct Zlib;
+
+impl Codec for Zlib {
+    fn id(&self) -> u8 {
+        6
+    }
+
+    fn name(&self) -> &'static str {
+        "zlib"
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+        algorithms::deflate::compress_zlib(data, level)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+        algorithms::deflate::decompress_zlib(data, limit)
+    }
+
+    fn in_trials(&self) -> bool {
+        false
+    }
+
+    fn is_standard_stream(&self) -> bool {
+        true
+    }
+
+    fn is_stream(&self, prefix: &[u8]) -> bool {
+        algorithms::deflate::is_zlib(prefix)
+    }
+
+    fn stream_encoder<'a>(&self, inner: Box<dyn Write + 'a>, level: u32) -> Box<dyn Encoder + 'a> {
+        let compression = algorithms::deflate::compression(level);
+        Box::new(flate2::write::ZlibEncoder::new(inner, compression))
+    }
+
+    fn stream_decoder<'a>(&self, inner: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(flate2::read::ZlibDecoder::new(inner))
+    }
+}
+
+struct Gzip;
+
+impl Codec for Gzip {
+    fn id(&self) -> u8 {
+        7
+    }
+
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+        algorithms::deflate::compress_gzip(data, level)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+        algorithms::deflate::decompress_gzip(data, limit)
+    }
+
+    fn in_trials(&self) -> bool {
+        false
+    }
+
+    fn is_standard_stream(&self) -> bool {
+        true
+    }
+
+    fn is_stream(&self, prefix: &[u8]) -> bool {
+        algorithms::deflate::is_gzip(prefix)
+    }
+
+    fn stream_encoder<'a>(&self, inner: Box<dyn Write + 'a>, level: u32) -> Box<dyn Encoder + 'a> {
+        let compression = algorithms::deflate::compression(level);
+        Box::new(flate2::write::GzEncoder::new(inner, compression))
+    }
+
+    // gzip(1) concatenates members when appending; read all of them
+    fn stream_decoder<'a>(&self, inner: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        Box::new(flate2::read::MultiGzDecoder::new(inner))
+    }
+}
+
+// Copies data as is. Blocks and streams no codec can shrink fall back to
+// it; the fallback already covers auto mode, so it isn't tried.
+struct Stored;
+
+impl Codec for Stored {
+    fn id(&self) -> u8 {
+        9
+    }
+
+    fn name(&self) -> &'static str {
+        "stored"
+    }
+
+    fn compress(&self, data: &[u8], _level: u32) -> Result<Vec<u8>, String> {
+        Ok(data.to_vec())
+    }
+
+    fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, String> {
+        Ok(data.to_vec())
+    }
+
+    fn in_trials(&self) -> bool {
+        false
+    }
+}
+
+// Trial-compresses a sample of the data with every candidate and encodes
+// all of it with the winner, prefixed by the winner's codec id.
+struct Auto;
+
+impl Codec for Auto {
+    fn id(&self) -> u8 {
+        8
+    }
+
+    fn name(&self) -> &'static str {
+        "auto"
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, String> {
+        let sample = crate::detect::sample(data);
+        let trial = crate::detect::trial_compress(&sample, level)?;
+        let mut output = vec![trial.algorithm.id()];
+        if sample.len() == data.len() {
+            // The sample was all of it, so the trial output is the result
+            output.extend_from_slice(&trial.packed);
+        } else {
+            output.extend_from_slice(&crate::encode(data, trial.algorithm, level)?);
+        }
+        Ok(output)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+        let (&id, payload) = data.split_first().ok_or("Missing codec id in auto payload")?;
+        match Algorithm::from_id(id) {
+            Some(Algorithm::Auto) | None => Err(format!("Invalid codec id {} in auto payload", id)),
+            Some(algorithm) => crate::decode(payload, algorithm, limit),
+        }
+    }
+
+    fn in_trials(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Drops trailing zeros and records how many there were: simple, and
+    // it shrinks the right input, so it doesn't fall back to stored.
+    struct TrailingZeros(u8, &'static str);
+
+    impl Codec for TrailingZeros {
+        fn id(&self) -> u8 {
+            self.0
+        }
+
+        fn name(&self) -> &'static str {
+            self.1
+        }
+
+        fn compress(&self, data: &[u8], _level: u32) -> Result<Vec<u8>, String> {
+            let kept = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            let mut output = ((data.len() - kept) as u32).to_le_bytes().to_vec();
+            output.extend_from_slice(&data[..kept]);
+            Ok(output)
+        }
+
+        fn decompress(&self, data: &[u8], _limit: usize) -> Result<Vec<u8>, String> {
+            let (count, kept) = data.split_at_checked(4).ok_or("Truncated zero count")?;
+            let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+            Ok([kept, &vec![0; count]].concat())
+        }
+
+        fn in_trials(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_builtins_match_algorithm_ids() {
+        for codec in codecs().iter().filter(|c| c.id() < MIN_CUSTOM_ID) {
+            let algorithm = Algorithm::from_id(codec.id()).unwrap();
+            assert_eq!(algorithm.name(), codec.name());
+            assert_eq!(Algorithm::from_name(codec.name()), Some(algorithm));
+            assert!(!matches!(algorithm, Algorithm::Custom(_)));
+        }
+        assert!(trial_candidates().iter().all(|c| !c.is_standard_stream()));
+    }
+
+    // Unregisters a test codec when dropped, even if the test fails, so the
+    // shared registry is the same for every test.
+    struct Registered(u8);
+
+    impl Registered {
+        fn new(codec: TrailingZeros) -> Self {
+            let id = codec.0;
+            register(codec).unwrap();
+            Registered(id)
+        }
+    }
+
+    impl Drop for Registered {
+        fn drop(&mut self) {
+            unregister(self.0);
+        }
+    }
+
+    #[test]
+    fn test_custom_codec_round_trip() {
+        let _codec = Registered::new(TrailingZeros(200, "trailing-zeros"));
+        let algorithm = Algorithm::from_name("trailing-zeros").unwrap();
+        assert_eq!(algorithm, Algorithm::Custom(200));
+        assert_eq!(algorithm.name(), "trailing-zeros");
+
+        let input = [b"header".to_vec(), vec![0; 1000]].concat();
+        let compressed = crate::compress(&input, algorithm).unwrap();
+        assert_eq!(compressed[5], 200);
+        assert_eq!(compressed.len(), crate::format::HEADER_LEN + 4 + 6);
+        assert_eq!(crate::decompress(&compressed).unwrap(), input);
+
+        let streamed = crate::compress_stream(&input[..], Vec::new(), algorithm, 6).unwrap();
+        assert_eq!(streamed[5], 200);
+        assert_eq!(crate::decompress_stream(&streamed[..], Vec::new()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_register_rejects_clashes() {
+        let err = register(TrailingZeros(3, "mine")).unwrap_err();
+        assert!(err.contains("reserved"), "{}", err);
+        let err = register(TrailingZeros(201, "lz77")).unwrap_err();
+        assert!(err.contains("clashes"), "{}", err);
+        assert!(register(TrailingZeros(202, "")).is_err());
+
+        let _codec = Registered::new(TrailingZeros(203, "zeros-again"));
+        assert!(register(TrailingZeros(203, "other-name")).is_err());
+    }
+
+    #[test]
+    fn test_unregister() {
+        let codec = Registered::new(TrailingZeros(204, "short-lived"));
+        assert!(Algorithm::from_name("short-lived").is_some());
+        drop(codec);
+        assert!(Algorithm::from_name("short-lived").is_none());
+        assert!(by_id(204).is_none());
+
+        assert!(unregister(2).is_none());
+        assert!(by_id(2).is_some());
+    }
+
+    #[test]
+    fn test_sniff_standard_streams() {
+        let gzip = crate::compress(b"sniffed", Algorithm::Gzip).unwrap();
+        assert_eq!(sniff(&gzip[..SNIFF_LEN]).unwrap().name(), "gzip");
+        let zlib = crate::compress(b"sniffed", Algorithm::Zlib).unwrap();
+        assert_eq!(sniff(&zlib).unwrap().name(), "zlib");
+        assert!(sniff(&crate::compress(b"sniffed", Algorithm::Lz77).unwrap()).is_none());
+    }
+}
@@ -1,32 +1,317 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::Path;
 
-pub fn detect_best_algorithm(file_path: &str) -> &'static str {
-    let path = Path::new(file_path);
-    
-    // Check file extension
-    if let Some(extension) = path.extension() {
-        match extension.to_str().unwrap_or("").to_lowercase().as_str() {
-            // Text files - LZ77 typically better for repeated patterns
-            "txt" | "log" | "md" | "csv" | "json" | "xml" | "html" | "css" | "js" => "lz",
-            
-            // Binary files - RLE often better for runs of repeated bytes
-            "bin" | "dat" | "exe" | "dll" => "rle",
-            
-            // Images - RLE better for images with large areas of same color
-            "bmp" | "tga" | "raw" => "rle",
-            
-            // Default to LZ77 for unknown types
-            _ => "lz"
+use crate::format::Algorithm;
+
+// How much of the input is looked at. Large files are sampled in a few
+// evenly spaced chunks so a header or a trailing index can't decide alone.
+pub const SAMPLE_LEN: usize = 64 * 1024;
+const SAMPLE_CHUNKS: u64 = 4;
+
+// Above this many bits per byte there is nothing left for a codec to find.
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+// Share of the sample covered by runs of RUN_MIN or more equal bytes before
+// run-length coding beats a dictionary coder.
+const RUN_MIN: usize = 4;
+const RUN_COVERAGE: f64 = 0.5;
+// Share of positions that start a 4-byte sequence already seen earlier.
+const REPEATED: f64 = 0.2;
+const REPEAT_WINDOW: usize = 4;
+
+// A file format recognized by its leading bytes. `magic` is expected at
+// `offset` from the start of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    pub name: &'static str,
+    pub offset: usize,
+    pub magic: &'static [u8],
+}
+
+const fn signature(name: &'static str, offset: usize, magic: &'static [u8]) -> Signature {
+    Signature { name, offset, magic }
+}
+
+// Formats that are already compressed, so another codec won't gain
+// anything on them.
+pub const SIGNATURES: &[Signature] = &[
+    // Images
+    signature("png", 0, b"\x89PNG\r\n\x1a\n"),
+    signature("jpeg", 0, b"\xff\xd8\xff"),
+    signature("gif", 0, b"GIF87a"),
+    signature("gif", 0, b"GIF89a"),
+    signature("webp", 8, b"WEBP"),
+    // Archives and compressed streams
+    signature("zip", 0, b"PK\x03\x04"),
+    signature("zip", 0, b"PK\x05\x06"),
+    signature("gzip", 0, b"\x1f\x8b"),
+    signature("bzip2", 0, b"BZh"),
+    signature("xz", 0, b"\xfd7zXZ\x00"),
+    signature("zstd", 0, b"\x28\xb5\x2f\xfd"),
+    signature("lz4", 0, b"\x04\x22\x4d\x18"),
+    signature("7z", 0, b"7z\xbc\xaf\x27\x1c"),
+    signature("rar", 0, b"Rar!\x1a\x07"),
+    signature("rzip", 0, &crate::format::MAGIC),
+    // Audio and video
+    signature("mp4", 4, b"ftyp"),
+    signature("matroska", 0, b"\x1a\x45\xdf\xa3"),
+    signature("mp3", 0, b"ID3"),
+    signature("ogg", 0, b"OggS"),
+    signature("flac", 0, b"fLaC"),
+];
+
+// Enough leading bytes to check every signature.
+pub const MAX_SIGNATURE_LEN: usize = 16;
+
+// Finds the first signature matching the start of `data`.
+pub fn sniff(data: &[u8]) -> Option<&'static Signature> {
+    SIGNATURES
+        .iter()
+        .find(|sig| data.get(sig.offset..sig.offset + sig.magic.len()) == Some(sig.magic))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ContentStats {
+    pub sample_len: usize,
+    // Shannon entropy in bits per byte, 0.0 to 8.0
+    pub entropy: f64,
+    // Average length of a run of equal bytes
+    pub mean_run: f64,
+    // Fraction of bytes inside runs of at least RUN_MIN
+    pub run_coverage: f64,
+    // Fraction of positions repeating an earlier 4-byte sequence
+    pub repeat_ratio: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub algorithm: Algorithm,
+    pub stats: ContentStats,
+    // Human-readable account of how the choice was made, in order
+    pub reasons: Vec<String>,
+}
+
+impl ContentStats {
+    pub fn analyze(sample: &[u8]) -> Self {
+        if sample.is_empty() {
+            return ContentStats::default();
+        }
+
+        let mut counts = [0usize; 256];
+        for &byte in sample {
+            counts[byte as usize] += 1;
         }
+        let len = sample.len() as f64;
+        let entropy = counts
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / len;
+                -p * p.log2()
+            })
+            .sum();
+
+        let mut runs = 0;
+        let mut in_long_runs = 0;
+        for run in sample.chunk_by(|a, b| a == b) {
+            runs += 1;
+            if run.len() >= RUN_MIN {
+                in_long_runs += run.len();
+            }
+        }
+
+        // A small direct-mapped table of the last position of each hashed
+        // 4-byte sequence; cheap, and close enough for a heuristic.
+        let mut repeats = 0;
+        let positions = sample.len().saturating_sub(REPEAT_WINDOW - 1);
+        let mut last_seen = vec![usize::MAX; 1 << 12];
+        for (pos, window) in sample.windows(REPEAT_WINDOW).enumerate() {
+            let key = u32::from_le_bytes([window[0], window[1], window[2], window[3]]);
+            let slot = (key.wrapping_mul(0x9E37_79B1) >> 20) as usize;
+            let previous = last_seen[slot];
+            if previous != usize::MAX && sample[previous..previous + REPEAT_WINDOW] == *window {
+                repeats += 1;
+            }
+            last_seen[slot] = pos;
+        }
+
+        ContentStats {
+            sample_len: sample.len(),
+            entropy,
+            mean_run: len / runs as f64,
+            run_coverage: in_long_runs as f64 / len,
+            repeat_ratio: if positions == 0 { 0.0 } else { repeats as f64 / positions as f64 },
+        }
+    }
+}
+
+// Picks a codec from the data itself. `file_name` only matters when the
+// statistics don't point clearly either way.
+pub fn detect_algorithm(sample: &[u8], file_name: Option<&str>) -> Detection {
+    let stats = ContentStats::analyze(sample);
+    let mut reasons = Vec::new();
+
+    let algorithm = if let Some(sig) = sniff(sample) {
+        reasons.push(format!("data starts like {}, which is already compressed", sig.name));
+        Algorithm::Stored
+    } else if stats.sample_len == 0 {
+        reasons.push("no data to sample".to_string());
+        extension_tiebreak(file_name, &mut reasons)
+    } else if stats.run_coverage >= RUN_COVERAGE {
+        reasons.push(format!(
+            "{:.0}% of bytes are in runs of {} or more (mean run {:.1})",
+            stats.run_coverage * 100.0,
+            RUN_MIN,
+            stats.mean_run
+        ));
+        // PackBits rather than plain RLE: it doesn't expand the literal
+        // stretches between runs.
+        Algorithm::PackBits
+    } else if stats.repeat_ratio >= REPEATED {
+        reasons.push(format!(
+            "{:.0}% of positions repeat earlier data",
+            stats.repeat_ratio * 100.0
+        ));
+        Algorithm::Lz77
+    } else if stats.entropy >= INCOMPRESSIBLE_ENTROPY {
+        reasons.push(format!(
+            "entropy of {:.2} bits per byte leaves little to compress",
+            stats.entropy
+        ));
+        // Nothing will shrink this, so don't spend time trying
+        Algorithm::Stored
     } else {
-        // No extension - default to LZ77
-        "lz"
+        reasons.push(format!(
+            "no strong signal (entropy {:.2}, {:.0}% in runs, {:.0}% repeated)",
+            stats.entropy,
+            stats.run_coverage * 100.0,
+            stats.repeat_ratio * 100.0
+        ));
+        extension_tiebreak(file_name, &mut reasons)
+    };
+
+    Detection { algorithm, stats, reasons }
+}
+
+// Reads a sample of the file and detects from that.
+pub fn detect_file(path: &Path) -> io::Result<Detection> {
+    let sample = read_sample(File::open(path)?)?;
+    Ok(detect_algorithm(&sample, path.to_str()))
+}
+
+// Up to SAMPLE_LEN bytes: all of a small input, or SAMPLE_CHUNKS chunks
+// spread evenly across a large one.
+pub fn read_sample<R: Read + Seek>(mut reader: R) -> io::Result<Vec<u8>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut sample = Vec::with_capacity(SAMPLE_LEN.min(len as usize));
+    if len <= SAMPLE_LEN as u64 {
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_to_end(&mut sample)?;
+        return Ok(sample);
+    }
+
+    let chunk = SAMPLE_LEN as u64 / SAMPLE_CHUNKS;
+    let stride = (len - chunk) / (SAMPLE_CHUNKS - 1);
+    for i in 0..SAMPLE_CHUNKS {
+        reader.seek(SeekFrom::Start(i * stride))?;
+        (&mut reader).take(chunk).read_to_end(&mut sample)?;
+    }
+    Ok(sample)
+}
+
+// Same as read_sample, for data already in memory.
+pub fn sample(data: &[u8]) -> Vec<u8> {
+    read_sample(io::Cursor::new(data)).expect("reading from memory can't fail")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    pub algorithm: Algorithm,
+    // Compressed size of the sample with each candidate, in trial order
+    pub sizes: Vec<(Algorithm, usize)>,
+    // The winner's output for the sample
+    pub packed: Vec<u8>,
+}
+
+// Compresses `sample` with every registered codec that takes part in trials
+// (see `codec::trial_candidates`) and keeps the smallest result. Ties go to
+// the earlier candidate.
+pub fn trial_compress(sample: &[u8], level: u32) -> Result<Trial, String> {
+    let candidates = crate::codec::trial_candidates();
+    let mut sizes = Vec::with_capacity(candidates.len());
+    let mut best: Option<(Algorithm, Vec<u8>)> = None;
+    for codec in candidates {
+        let algorithm = Algorithm::from_id(codec.id()).expect("candidates are registered");
+        let packed = codec.compress(sample, level)?;
+        sizes.push((algorithm, packed.len()));
+        if best.as_ref().is_none_or(|(_, smallest)| packed.len() < smallest.len()) {
+            best = Some((algorithm, packed));
+        }
+    }
+    let (algorithm, packed) = best.ok_or("No registered codec takes part in trials")?;
+    Ok(Trial { algorithm, sizes, packed })
+}
+
+fn extension_tiebreak(file_name: Option<&str>, reasons: &mut Vec<String>) -> Algorithm {
+    match file_name.and_then(extension_hint) {
+        Some(algorithm) => {
+            reasons.push(format!("file extension suggests {}", algorithm.name()));
+            algorithm
+        }
+        None => {
+            reasons.push("defaulting to lz77".to_string());
+            Algorithm::Lz77
+        }
+    }
+}
+
+// The extension table on its own, for inputs that can't be sampled.
+// Unknown extensions give None.
+pub fn extension_hint(file_path: &str) -> Option<Algorithm> {
+    let extension = Path::new(file_path).extension()?;
+    match extension.to_str().unwrap_or("").to_lowercase().as_str() {
+        // Text files - LZ77 typically better for repeated patterns
+        "txt" | "log" | "md" | "csv" | "json" | "xml" | "html" | "css" | "js" => Some(Algorithm::Lz77),
+
+        // Binaries and raw images often have long runs of one byte; they
+        // get the PackBits variant of RLE, which doesn't expand the rest
+        "bin" | "dat" | "exe" | "dll" | "bmp" | "tga" | "raw" => Some(Algorithm::PackBits),
+
+        _ => None,
+    }
+}
+
+// Extension-only lookup, kept for callers that want the old "rle"/"lz"
+// answer. Prefer detect_file, which looks at the data.
+pub fn detect_best_algorithm(file_path: &str) -> &'static str {
+    match extension_hint(file_path) {
+        Some(Algorithm::PackBits) => "rle",
+        _ => "lz",
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Cursor;
+
+    fn text_sample() -> Vec<u8> {
+        "The quick brown fox jumps over the lazy dog. Pack my box with five dozen liquor jugs.\n"
+            .repeat(200)
+            .into_bytes()
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
 
     #[test]
     fn test_detect_text_files() {
@@ -53,4 +338,103 @@ mod tests {
         assert_eq!(detect_best_algorithm("unknown.xyz"), "lz");
         assert_eq!(detect_best_algorithm("noextension"), "lz");
     }
+
+    #[test]
+    fn test_stats() {
+        let stats = ContentStats::analyze(&[7; 1000]);
+        assert_eq!(stats.entropy, 0.0);
+        assert_eq!(stats.mean_run, 1000.0);
+        assert_eq!(stats.run_coverage, 1.0);
+
+        let stats = ContentStats::analyze(&noise(SAMPLE_LEN));
+        assert!(stats.entropy > 7.9, "{:?}", stats);
+        assert!(stats.repeat_ratio < 0.01, "{:?}", stats);
+        assert!(stats.mean_run < 1.1, "{:?}", stats);
+
+        assert_eq!(ContentStats::analyze(&[]), ContentStats::default());
+    }
+
+    #[test]
+    fn test_content_beats_extension() {
+        // Text in a .bin used to get RLE and double in size
+        let detection = detect_algorithm(&text_sample(), Some("notes.bin"));
+        assert_eq!(detection.algorithm, Algorithm::Lz77);
+        assert!(detection.reasons[0].contains("repeat"), "{:?}", detection.reasons);
+
+        let mut runs = Vec::new();
+        for i in 0..100u8 {
+            runs.extend(std::iter::repeat_n(i, 50));
+            runs.extend_from_slice(&[1, 2, 3]);
+        }
+        assert_eq!(detect_algorithm(&runs, Some("image.txt")).algorithm, Algorithm::PackBits);
+
+        let detection = detect_algorithm(&noise(4096), Some("archive.txt"));
+        assert_eq!(detection.algorithm, Algorithm::Stored);
+        assert!(detection.reasons[0].contains("entropy"), "{:?}", detection.reasons);
+    }
+
+    #[test]
+    fn test_extension_breaks_ties() {
+        // Varied bytes with moderate entropy and no repetition to speak of
+        let sample: Vec<u8> = noise(2048).iter().map(|b| b % 64).collect();
+        let detection = detect_algorithm(&sample, Some("frame.raw"));
+        assert_eq!(detection.algorithm, Algorithm::PackBits);
+        assert!(detection.reasons.last().unwrap().contains("extension"));
+        assert_eq!(detect_algorithm(&sample, Some("frame")).algorithm, Algorithm::Lz77);
+
+        assert_eq!(detect_algorithm(&[], Some("empty.dat")).algorithm, Algorithm::PackBits);
+        assert_eq!(detect_algorithm(&[], None).algorithm, Algorithm::Lz77);
+    }
+
+    #[test]
+    fn test_trial_picks_smallest() {
+        let mut runs = Vec::new();
+        for i in 0..64u8 {
+            runs.extend(std::iter::repeat_n(i, 200));
+        }
+        let trial = trial_compress(&runs, 6).unwrap();
+        assert_eq!(trial.sizes.len(), crate::codec::trial_candidates().len());
+        let smallest = trial.sizes.iter().map(|&(_, size)| size).min().unwrap();
+        assert_eq!(trial.packed.len(), smallest);
+        assert_eq!(crate::decode(&trial.packed, trial.algorithm, runs.len()).unwrap(), runs);
+
+        // Text compresses far better with a dictionary coder than with runs
+        let trial = trial_compress(&text_sample(), 6).unwrap();
+        assert!(!matches!(trial.algorithm, Algorithm::Rle | Algorithm::PackBits));
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap().name, "png");
+        assert_eq!(sniff(b"PK\x03\x04\x14\0").unwrap().name, "zip");
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 ").unwrap().name, "webp");
+        assert_eq!(sniff(b"\0\0\0\x20ftypisom").unwrap().name, "mp4");
+        assert_eq!(sniff(&crate::compress(b"ours", Algorithm::Lz77).unwrap()).unwrap().name, "rzip");
+        assert!(sniff(b"plain text").is_none());
+        assert!(sniff(b"PK").is_none());
+        assert!(sniff(b"").is_none());
+        assert!(SIGNATURES.iter().all(|sig| sig.offset + sig.magic.len() <= MAX_SIGNATURE_LEN));
+
+        // A signature wins over statistics that look compressible
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&text_sample());
+        let detection = detect_algorithm(&png, Some("notes.txt"));
+        assert_eq!(detection.algorithm, Algorithm::Stored);
+        assert!(detection.reasons[0].contains("png"), "{:?}", detection.reasons);
+    }
+
+    #[test]
+    fn test_read_sample() {
+        let small = text_sample();
+        assert_eq!(read_sample(Cursor::new(&small)).unwrap(), small);
+
+        // Runs at the end of a large input still show up in the sample
+        let mut large = noise(SAMPLE_LEN * 4);
+        let len = large.len();
+        large[len - 16 * 1024..].fill(0);
+        let sample = read_sample(Cursor::new(&large)).unwrap();
+        assert_eq!(sample.len(), SAMPLE_LEN);
+        assert_eq!(&sample[..1024], &large[..1024]);
+        assert!(sample.ends_with(&[0; 1024]));
+    }
 }
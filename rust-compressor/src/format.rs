@@ -0,0 +1,307 @@
+// Container header written in front of every compressed stream.
+//
+// Layout (all integers little-endian):
+//
+//   magic          4 bytes  "RZIP"
+//   version        1 byte
+//   codec id       1 byte
+//   flags          1 byte
+//   original size  8 bytes
+//   checksum       4 bytes  CRC32 of the uncompressed data
+//
+// When FLAG_BLOCKS is set the payload is a sequence of independently
+// compressed blocks written by `io::stream::CompressWriter`:
+//
+//   raw length     4 bytes  0 marks the end of the stream
+//   packed length  4 bytes
+//   checksum       4 bytes  CRC32 of the block's uncompressed data, only
+//                           present when FLAG_BLOCK_CHECKSUMS is set
+//   data
+//
+// With FLAG_STORED_BLOCKS a block whose packed length equals its raw length
+// holds the data uncompressed; the writer stores any block the codec
+// doesn't shrink.
+//
+// The end marker is followed by the total size (8 bytes) and the CRC32 of
+// all data (4 bytes); the header's size and checksum fields are zero since
+// they aren't known when it is written.
+//
+// Version 1 streams were never blocked, and their LZ77 payloads use the
+// original byte-sized token format (see `lz77::decompress_lz77_legacy`).
+// Version 2 switched LZ77 to LEB128 tokens; nothing else changed.
+//
+// With the auto codec every payload (the whole stream, or each block when
+// blocked) starts with the id of the codec that was picked for it, followed
+// by that codec's output.
+
+pub const MAGIC: [u8; 4] = *b"RZIP";
+pub const FORMAT_VERSION: u8 = 2;
+pub const HEADER_LEN: usize = 19;
+
+pub const FLAG_BLOCKS: u8 = 0x01;
+pub const FLAG_BLOCK_CHECKSUMS: u8 = 0x02;
+pub const FLAG_STORED_BLOCKS: u8 = 0x04;
+
+// Any other set bit is rejected so that future extensions are not silently
+// misread by older builds.
+pub const KNOWN_FLAGS: u8 = FLAG_BLOCKS | FLAG_BLOCK_CHECKSUMS | FLAG_STORED_BLOCKS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Rle,
+    Lz77,
+    Lzh,
+    PackBits,
+    Deflate,
+    Zlib,
+    Gzip,
+    // Picks a codec per payload by trial compression
+    Auto,
+    // No compression; used when a codec would expand the data
+    Stored,
+    // A codec registered through `codec::register`, by id
+    Custom(u8),
+}
+
+impl Algorithm {
+    pub fn id(self) -> u8 {
+        match self {
+            Algorithm::Rle => 1,
+            Algorithm::Lz77 => 2,
+            Algorithm::Lzh => 3,
+            Algorithm::PackBits => 4,
+            Algorithm::Deflate => 5,
+            Algorithm::Zlib => 6,
+            Algorithm::Gzip => 7,
+            Algorithm::Auto => 8,
+            Algorithm::Stored => 9,
+            Algorithm::Custom(id) => id,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Algorithm::Rle),
+            2 => Some(Algorithm::Lz77),
+            3 => Some(Algorithm::Lzh),
+            4 => Some(Algorithm::PackBits),
+            5 => Some(Algorithm::Deflate),
+            6 => Some(Algorithm::Zlib),
+            7 => Some(Algorithm::Gzip),
+            8 => Some(Algorithm::Auto),
+            9 => Some(Algorithm::Stored),
+            id if id >= crate::codec::MIN_CUSTOM_ID => crate::codec::by_id(id).map(|_| Algorithm::Custom(id)),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Rle => "rle",
+            Algorithm::Lz77 => "lz77",
+            Algorithm::Lzh => "lzh",
+            Algorithm::PackBits => "packbits",
+            Algorithm::Deflate => "deflate",
+            Algorithm::Zlib => "zlib",
+            Algorithm::Gzip => "gzip",
+            Algorithm::Auto => "auto",
+            Algorithm::Stored => "stored",
+            Algorithm::Custom(id) => crate::codec::by_id(id).map_or("unknown", |codec| codec.name()),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        crate::codec::by_name(name).and_then(|codec| Algorithm::from_id(codec.id()))
+    }
+
+    // Whether single streams are written in the codec's own format rather
+    // than inside the container; see `Codec::is_standard_stream`.
+    pub fn is_standard_stream(self) -> bool {
+        crate::codec::lookup(self).is_ok_and(|codec| codec.is_standard_stream())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub flags: u8,
+    pub original_size: u64,
+    pub checksum: u32,
+}
+
+impl Header {
+    pub fn new(algorithm: Algorithm, data: &[u8]) -> Self {
+        Header {
+            version: FORMAT_VERSION,
+            algorithm,
+            flags: 0,
+            original_size: data.len() as u64,
+            checksum: crc32fast::hash(data),
+        }
+    }
+
+    pub fn blocked(algorithm: Algorithm) -> Self {
+        Header {
+            version: FORMAT_VERSION,
+            algorithm,
+            flags: FLAG_BLOCKS | FLAG_BLOCK_CHECKSUMS | FLAG_STORED_BLOCKS,
+            original_size: 0,
+            checksum: 0,
+        }
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.flags & FLAG_BLOCKS != 0
+    }
+
+    pub fn has_block_checksums(&self) -> bool {
+        self.flags & FLAG_BLOCK_CHECKSUMS != 0
+    }
+
+    pub fn has_stored_blocks(&self) -> bool {
+        self.flags & FLAG_STORED_BLOCKS != 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0u8; HEADER_LEN];
+        out[0..4].copy_from_slice(&MAGIC);
+        out[4] = self.version;
+        out[5] = self.algorithm.id();
+        out[6] = self.flags;
+        out[7..15].copy_from_slice(&self.original_size.to_le_bytes());
+        out[15..19].copy_from_slice(&self.checksum.to_le_bytes());
+        out
+    }
+
+    // Parses the header at the start of `data` and returns it together with
+    // the payload that follows.
+    pub fn parse(data: &[u8]) -> Result<(Header, &[u8]), String> {
+        if !has_magic(data) {
+            return Err("Not a rust-compressor stream (missing RZIP magic bytes)".to_string());
+        }
+        if data.len() < HEADER_LEN {
+            return Err("Truncated header".to_string());
+        }
+
+        let version = data[4];
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(format!("Unsupported format version {}", version));
+        }
+
+        let algorithm = Algorithm::from_id(data[5])
+            .ok_or_else(|| format!("Unknown codec id {}", data[5]))?;
+
+        let flags = data[6];
+        if flags & !KNOWN_FLAGS != 0 || (version == 1 && flags != 0) {
+            return Err(format!("Unsupported header flags {:#04x}", flags));
+        }
+
+        let original_size = u64::from_le_bytes(data[7..15].try_into().unwrap());
+        let checksum = u32::from_le_bytes(data[15..19].try_into().unwrap());
+
+        let header = Header {
+            version,
+            algorithm,
+            flags,
+            original_size,
+            checksum,
+        };
+        Ok((header, &data[HEADER_LEN..]))
+    }
+
+    // Most output a decoder may produce for this stream; a recorded size
+    // too large for memory can't be met anyway.
+    pub fn size_limit(&self) -> usize {
+        usize::try_from(self.original_size).unwrap_or(usize::MAX)
+    }
+
+    // Checks decompressed output against the size and checksum recorded in
+    // the header.
+    pub fn verify(&self, data: &[u8]) -> Result<(), String> {
+        if data.len() as u64 != self.original_size {
+            return Err(format!(
+                "Size mismatch: expected {} bytes, got {}",
+                self.original_size,
+                data.len()
+            ));
+        }
+        let actual = crc32fast::hash(data);
+        if actual != self.checksum {
+            return Err(format!(
+                "Checksum mismatch: expected {:08x}, got {:08x}",
+                self.checksum, actual
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn has_magic(data: &[u8]) -> bool {
+    data.len() >= MAGIC.len() && data[..MAGIC.len()] == MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = Header::new(Algorithm::Lz77, b"Hello, World!");
+        let bytes = header.to_bytes();
+        let (parsed, payload) = Header::parse(&bytes).unwrap();
+        assert_eq!(parsed, header);
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn test_blocked_header_round_trip() {
+        let header = Header::blocked(Algorithm::Lzh);
+        let (parsed, _) = Header::parse(&header.to_bytes()).unwrap();
+        assert!(parsed.is_blocked());
+        assert_eq!(parsed.algorithm, Algorithm::Lzh);
+    }
+
+    #[test]
+    fn test_rejects_unknown_flags() {
+        let mut bytes = Header::new(Algorithm::Rle, b"abc").to_bytes();
+        bytes[6] = 0x80;
+        assert!(Header::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_rejects_future_versions() {
+        let mut bytes = Header::new(Algorithm::Rle, b"abc").to_bytes();
+        bytes[4] = FORMAT_VERSION + 1;
+        assert!(Header::parse(&bytes).unwrap_err().contains("version"));
+        bytes[4] = 1;
+        assert_eq!(Header::parse(&bytes).unwrap().0.version, 1);
+    }
+
+    #[test]
+    fn test_rejects_foreign_data() {
+        let err = Header::parse(b"PK\x03\x04 not ours at all").unwrap_err();
+        assert!(err.contains("magic"));
+    }
+
+    #[test]
+    fn test_rejects_unknown_codec() {
+        let mut bytes = Header::new(Algorithm::Rle, b"abc").to_bytes();
+        bytes[5] = 0xee;
+        assert!(Header::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_rejects_truncated_header() {
+        let bytes = Header::new(Algorithm::Rle, b"abc").to_bytes();
+        assert!(Header::parse(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let header = Header::new(Algorithm::Rle, b"abc");
+        assert!(header.verify(b"abc").is_ok());
+        assert!(header.verify(b"abd").is_err());
+        assert!(header.verify(b"ab").is_err());
+    }
+}
@@ -0,0 +1,470 @@
+// File metadata stored with archive entries and restored on extraction.
+//
+// Serialized layout (all integers little-endian):
+//
+//   mode           4 bytes  Unix permission and file type bits
+//   mtime          8 bytes  seconds since the Unix epoch, signed
+//   mtime nanos    4 bytes
+//   uid            4 bytes
+//   gid            4 bytes
+//   user length    1 byte, then the user name (empty if unknown)
+//   group length   1 byte, then the group name (empty if unknown)
+//
+// On extraction ownership is looked up by name first and falls back to the
+// numeric ids, so archives move sensibly between machines.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub mode: u32,
+    pub mtime: i64,
+    pub mtime_nanos: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub user: String,
+    pub group: String,
+}
+
+// How much of the stored metadata to apply when restoring a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestoreOptions {
+    // Restore the stored owner and group; only possible as root.
+    pub same_owner: bool,
+    // Apply the stored mode as is, including setuid, setgid and sticky
+    // bits. Otherwise the permission bits are masked by the umask.
+    pub preserve_permissions: bool,
+}
+
+impl Default for RestoreOptions {
+    // Mirrors tar: ownership is restored when running as root.
+    fn default() -> Self {
+        RestoreOptions {
+            same_owner: is_root(),
+            preserve_permissions: false,
+        }
+    }
+}
+
+impl EntryMetadata {
+    pub fn from_fs(meta: &fs::Metadata) -> Self {
+        let (mtime, mtime_nanos) = match meta.modified() {
+            Ok(time) => split_time(time),
+            Err(_) => (0, 0),
+        };
+        let mut metadata = EntryMetadata {
+            mtime,
+            mtime_nanos,
+            ..EntryMetadata::default()
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            metadata.mode = meta.mode();
+            metadata.uid = meta.uid();
+            metadata.gid = meta.gid();
+            metadata.user = unix::user_name(meta.uid()).unwrap_or_default();
+            metadata.group = unix::group_name(meta.gid()).unwrap_or_default();
+        }
+        #[cfg(not(unix))]
+        {
+            metadata.mode = if meta.permissions().readonly() { 0o444 } else { 0o644 };
+        }
+
+        metadata
+    }
+
+    // Fails for times the platform can't represent, which only crafted
+    // archives contain.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        let nanos = Duration::from_nanos(self.mtime_nanos as u64);
+        let secs = Duration::from_secs(self.mtime.unsigned_abs());
+        let time = if self.mtime >= 0 { UNIX_EPOCH.checked_add(secs) } else { UNIX_EPOCH.checked_sub(secs) };
+        time.and_then(|time| time.checked_add(nanos)).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Modification time {}.{:09} is out of range", self.mtime, self.mtime_nanos),
+            )
+        })
+    }
+
+    // Permission bits as `ls` shows them after the type character.
+    pub fn permissions_string(&self) -> String {
+        let mut out = String::with_capacity(9);
+        for shift in [6, 3, 0] {
+            let bits = (self.mode >> shift) & 0o7;
+            out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            out.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        out
+    }
+
+    // Modification time as `YYYY-MM-DD HH:MM:SS` in UTC.
+    pub fn format_mtime(&self) -> String {
+        let days = self.mtime.div_euclid(86_400);
+        let secs = self.mtime.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.mode.to_le_bytes())?;
+        writer.write_all(&self.mtime.to_le_bytes())?;
+        writer.write_all(&self.mtime_nanos.to_le_bytes())?;
+        writer.write_all(&self.uid.to_le_bytes())?;
+        writer.write_all(&self.gid.to_le_bytes())?;
+        for name in [&self.user, &self.group] {
+            // Names longer than 255 bytes can't be stored; the numeric id
+            // still is.
+            let name = if name.len() > u8::MAX as usize { "" } else { name.as_str() };
+            writer.write_all(&[name.len() as u8])?;
+            writer.write_all(name.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut fixed = [0u8; 24];
+        reader.read_exact(&mut fixed)?;
+        let mut names = [String::new(), String::new()];
+        for name in &mut names {
+            let mut len = [0u8; 1];
+            reader.read_exact(&mut len)?;
+            let mut buf = vec![0u8; len[0] as usize];
+            reader.read_exact(&mut buf)?;
+            *name = String::from_utf8(buf)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Owner name is not valid UTF-8"))?;
+        }
+        let [user, group] = names;
+
+        let metadata = EntryMetadata {
+            mode: u32::from_le_bytes(fixed[0..4].try_into().unwrap()),
+            mtime: i64::from_le_bytes(fixed[4..12].try_into().unwrap()),
+            mtime_nanos: u32::from_le_bytes(fixed[12..16].try_into().unwrap()),
+            uid: u32::from_le_bytes(fixed[16..20].try_into().unwrap()),
+            gid: u32::from_le_bytes(fixed[20..24].try_into().unwrap()),
+            user,
+            group,
+        };
+        if metadata.mtime_nanos >= 1_000_000_000 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid modification time nanoseconds {}", metadata.mtime_nanos),
+            ));
+        }
+        metadata.modified()?;
+        Ok(metadata)
+    }
+
+    // Number of bytes `write_to` produces.
+    pub fn encoded_len(&self) -> usize {
+        let name_len = |name: &str| if name.len() > u8::MAX as usize { 0 } else { name.len() };
+        24 + 1 + name_len(&self.user) + 1 + name_len(&self.group)
+    }
+
+    // Applies ownership, permissions and modification time to the freshly
+    // extracted file or directory at `path`.
+    pub fn restore(&self, path: &Path, options: &RestoreOptions) -> io::Result<()> {
+        // Setting the time only needs ownership on Unix, so a read-only
+        // handle also works for directories.
+        #[cfg(unix)]
+        let file = File::open(path)?;
+        #[cfg(not(unix))]
+        let file = File::options().write(true).open(path)?;
+        file.set_modified(self.modified()?)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            // Ownership first: chown clears the setuid and setgid bits.
+            if options.same_owner {
+                let uid = unix::user_id(&self.user).unwrap_or(self.uid);
+                let gid = unix::group_id(&self.group).unwrap_or(self.gid);
+                std::os::unix::fs::chown(path, Some(uid), Some(gid))?;
+            }
+
+            let mode = if options.preserve_permissions {
+                self.mode & 0o7777
+            } else {
+                self.mode & 0o777 & !unix::umask()
+            };
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = options;
+            let mut permissions = fs::metadata(path)?.permissions();
+            permissions.set_readonly(self.mode & 0o222 == 0);
+            fs::set_permissions(path, permissions)?;
+        }
+        Ok(())
+    }
+
+    // Symlinks have no permissions of their own, so only ownership and the
+    // modification time are applied, to the link rather than its target.
+    #[cfg(unix)]
+    pub fn restore_symlink(&self, path: &Path, options: &RestoreOptions) -> io::Result<()> {
+        if options.same_owner {
+            let uid = unix::user_id(&self.user).unwrap_or(self.uid);
+            let gid = unix::group_id(&self.group).unwrap_or(self.gid);
+            std::os::unix::fs::lchown(path, Some(uid), Some(gid))?;
+        }
+        unix::set_symlink_mtime(path, self.mtime, self.mtime_nanos)
+    }
+}
+
+fn split_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            // Before the epoch: round the seconds down so the nanoseconds
+            // stay positive.
+            let d = e.duration();
+            let secs = -(d.as_secs() as i64);
+            match d.subsec_nanos() {
+                0 => (secs, 0),
+                n => (secs - 1, 1_000_000_000 - n),
+            }
+        }
+    }
+}
+
+// Converts days since 1970-01-01 to a (year, month, day) date in the
+// proleptic Gregorian calendar (Howard Hinnant's algorithm).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and can't fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::OnceLock;
+
+    // Large enough for typical passwd and group entries; lookups retry
+    // with a bigger buffer on ERANGE.
+    const INITIAL_BUFFER: usize = 1024;
+    const MAX_BUFFER: usize = 1 << 20;
+
+    // Linux reports the umask in /proc/self/status. Elsewhere the only way
+    // to read it is to swap it out and back, and a file another thread
+    // creates in between gets the wrong mode; that is done once, on first
+    // use, and the CLI extracts on a single thread.
+    pub fn umask() -> u32 {
+        static UMASK: OnceLock<u32> = OnceLock::new();
+        *UMASK.get_or_init(|| {
+            proc_umask().unwrap_or_else(|| {
+                // SAFETY: umask only swaps the process file mode mask, and
+                // it is set back immediately.
+                unsafe {
+                    let mask = libc::umask(0o022);
+                    libc::umask(mask);
+                    mask as u32
+                }
+            })
+        })
+    }
+
+    pub fn proc_umask() -> Option<u32> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find_map(|line| line.strip_prefix("Umask:"))?;
+        u32::from_str_radix(line.trim(), 8).ok()
+    }
+
+    pub fn set_symlink_mtime(path: &Path, secs: i64, nanos: u32) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let times = [
+            libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },
+            libc::timespec { tv_sec: secs as libc::time_t, tv_nsec: nanos as _ },
+        ];
+        // SAFETY: `path` is NUL-terminated and `times` holds the two
+        // timestamps utimensat expects.
+        let rc = unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        with_buffer(|buf| {
+            let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            // SAFETY: all pointers are valid for the duration of the call
+            // and `buf` is as long as the length passed.
+            let rc = unsafe { libc::getpwuid_r(uid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+            (rc, (!result.is_null()).then(|| unsafe { CStr::from_ptr(entry.pw_name) }.to_string_lossy().into_owned()))
+        })
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        with_buffer(|buf| {
+            let mut entry: libc::group = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            // SAFETY: as in `user_name`.
+            let rc = unsafe { libc::getgrgid_r(gid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+            (rc, (!result.is_null()).then(|| unsafe { CStr::from_ptr(entry.gr_name) }.to_string_lossy().into_owned()))
+        })
+    }
+
+    pub fn user_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok().filter(|n| !n.as_bytes().is_empty())?;
+        with_buffer(|buf| {
+            let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            // SAFETY: as in `user_name`; `name` is NUL-terminated.
+            let rc = unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+            (rc, (!result.is_null()).then_some(entry.pw_uid))
+        })
+    }
+
+    pub fn group_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok().filter(|n| !n.as_bytes().is_empty())?;
+        with_buffer(|buf| {
+            let mut entry: libc::group = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            // SAFETY: as in `user_id`.
+            let rc = unsafe { libc::getgrnam_r(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+            (rc, (!result.is_null()).then_some(entry.gr_gid))
+        })
+    }
+
+    // Runs a reentrant passwd/group lookup, growing the scratch buffer
+    // while the lookup reports ERANGE.
+    fn with_buffer<T>(mut lookup: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<T>)) -> Option<T> {
+        let mut size = INITIAL_BUFFER;
+        loop {
+            let mut buf = vec![0 as libc::c_char; size];
+            match lookup(&mut buf) {
+                (libc::ERANGE, _) if size < MAX_BUFFER => size *= 2,
+                (_, value) => return value,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_encoding() {
+        let metadata = EntryMetadata {
+            mode: 0o100755,
+            mtime: 1_700_000_000,
+            mtime_nanos: 123_456_789,
+            uid: 1000,
+            gid: 100,
+            user: "alice".to_string(),
+            group: "users".to_string(),
+        };
+        let mut bytes = Vec::new();
+        metadata.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), metadata.encoded_len());
+        assert_eq!(EntryMetadata::read_from(&mut &bytes[..]).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_times_before_epoch() {
+        let time = UNIX_EPOCH - Duration::from_millis(1500);
+        let (mtime, mtime_nanos) = split_time(time);
+        assert_eq!((mtime, mtime_nanos), (-2, 500_000_000));
+        let metadata = EntryMetadata { mtime, mtime_nanos, ..EntryMetadata::default() };
+        assert_eq!(metadata.modified().unwrap(), time);
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_times() {
+        for (mtime, mtime_nanos) in [(i64::MAX, 2_000_000_000), (0, 1_000_000_000)] {
+            let metadata = EntryMetadata { mtime, mtime_nanos, ..EntryMetadata::default() };
+            let mut bytes = Vec::new();
+            metadata.write_to(&mut bytes).unwrap();
+            let err = EntryMetadata::read_from(&mut &bytes[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        let metadata = EntryMetadata { mtime: i64::MAX, mtime_nanos: 2_000_000_000, ..EntryMetadata::default() };
+        assert!(metadata.modified().is_err());
+    }
+
+    #[test]
+    fn test_format_mtime() {
+        let at = |mtime| EntryMetadata { mtime, ..EntryMetadata::default() }.format_mtime();
+        assert_eq!(at(0), "1970-01-01 00:00:00");
+        assert_eq!(at(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(at(1_700_000_000), "2023-11-14 22:13:20");
+        assert_eq!(at(-1), "1969-12-31 23:59:59");
+    }
+
+    #[test]
+    fn test_permissions_string() {
+        let metadata = EntryMetadata { mode: 0o100640, ..EntryMetadata::default() };
+        assert_eq!(metadata.permissions_string(), "rw-r-----");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_umask_read_without_changing_it() {
+        let mask = unix::proc_umask().unwrap();
+        assert_eq!(mask & !0o777, 0);
+        assert_eq!(unix::umask(), mask);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_mode_and_mtime() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, b"x").unwrap();
+        let metadata = EntryMetadata {
+            mode: 0o100751,
+            mtime: 1_000_000_000,
+            mtime_nanos: 42,
+            ..EntryMetadata::from_fs(&fs::metadata(&path).unwrap())
+        };
+        let options = RestoreOptions { same_owner: false, preserve_permissions: true };
+        metadata.restore(&path, &options).unwrap();
+
+        let restored = fs::metadata(&path).unwrap();
+        assert_eq!(restored.permissions().mode() & 0o7777, 0o751);
+        assert_eq!(restored.modified().unwrap(), metadata.modified().unwrap());
+        assert_eq!(EntryMetadata::from_fs(&restored).mtime, 1_000_000_000);
+    }
+}
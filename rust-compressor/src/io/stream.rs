@@ -1,5 +1,15 @@
 use std::io::{self, Read, Write};
 
+use crate::format::{self, Algorithm, Header};
+
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+// Upper bound accepted for a single block when decoding. Each block's raw
+// length is passed to its decoder as the output limit; the LZ and deflate
+// decoders fail as soon as they would go past it, and RLE and PackBits can
+// expand the (bounded) packed data by at most 128 and 64 times.
+const MAX_BLOCK_SIZE: usize = 64 << 20;
+
 pub fn read_stream<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
     let mut buffer = Vec::new();
     reader.read_to_end(&mut buffer)?;
@@ -11,6 +21,312 @@ pub fn write_stream<W: Write>(mut writer: W, data: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Compresses everything written to it into a blocked stream on `W`. Input is
+// buffered one block at a time, so memory use doesn't depend on the total
+// size. Call `finish` to write the trailer; dropping the writer finishes it
+// too but swallows any error.
+pub struct CompressWriter<W: Write> {
+    inner: Option<W>,
+    algorithm: Algorithm,
+    level: u32,
+    block_size: usize,
+    buffer: Vec<u8>,
+    hasher: crc32fast::Hasher,
+    total: u64,
+    header_written: bool,
+    // Set once the trailer has been attempted, so a failed `finish` doesn't
+    // get a second trailer appended on drop.
+    finished: bool,
+}
+
+impl<W: Write> CompressWriter<W> {
+    pub fn new(inner: W, algorithm: Algorithm, level: u32) -> Self {
+        Self::with_block_size(inner, algorithm, level, DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(inner: W, algorithm: Algorithm, level: u32, block_size: usize) -> Self {
+        assert!(
+            block_size > 0 && block_size <= MAX_BLOCK_SIZE,
+            "block size must be between 1 and {} bytes",
+            MAX_BLOCK_SIZE
+        );
+        CompressWriter {
+            inner: Some(inner),
+            algorithm,
+            level,
+            block_size,
+            buffer: Vec::with_capacity(block_size),
+            hasher: crc32fast::Hasher::new(),
+            total: 0,
+            header_written: false,
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let header = Header::blocked(self.algorithm);
+            self.inner.as_mut().unwrap().write_all(&header.to_bytes())?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        // A block the codec doesn't shrink is written as is; its packed
+        // length equal to the raw length tells the reader.
+        let packed = crate::encode(&self.buffer, self.algorithm, self.level).map_err(io::Error::other)?;
+        let packed = if packed.len() < self.buffer.len() { &packed } else { &self.buffer };
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+        inner.write_all(&(packed.len() as u32).to_le_bytes())?;
+        inner.write_all(&crc32fast::hash(&self.buffer).to_le_bytes())?;
+        inner.write_all(packed)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn write_trailer(&mut self) -> io::Result<()> {
+        self.finished = true;
+        self.write_block()?;
+        let checksum = self.hasher.clone().finalize();
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&0u32.to_le_bytes())?;
+        inner.write_all(&self.total.to_le_bytes())?;
+        inner.write_all(&checksum.to_le_bytes())?;
+        inner.flush()
+    }
+
+    // Writes the remaining data and the trailer and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_trailer()?;
+        Ok(self.inner.take().unwrap())
+    }
+}
+
+impl<W: Write> Write for CompressWriter<W> {
+    // A full block is written out before taking more data, so an error
+    // always means nothing from `buf` was consumed.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() == self.block_size {
+            self.write_block()?;
+        }
+        let n = buf.len().min(self.block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        self.hasher.update(&buf[..n]);
+        self.total += n as u64;
+        Ok(n)
+    }
+
+    // Emits the buffered data as a (possibly short) block so that everything
+    // written so far can be decoded by the other end of a pipe.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for CompressWriter<W> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.write_trailer();
+        }
+    }
+}
+
+// A streaming compressor as returned by `Codec::stream_encoder`. `finish`
+// writes whatever trailer the format needs; dropping an encoder without
+// calling it may leave the stream incomplete.
+pub trait Encoder: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl<W: Write> Encoder for CompressWriter<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        CompressWriter::finish(*self).map(drop)
+    }
+}
+
+impl<W: Write> Encoder for flate2::write::ZlibEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        flate2::write::ZlibEncoder::finish(*self).map(drop)
+    }
+}
+
+impl<W: Write> Encoder for flate2::write::GzEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        flate2::write::GzEncoder::finish(*self).map(drop)
+    }
+}
+
+// Compresses everything written to it with the registered codec for
+// `algorithm`: in the codec's own stream format if it has one, otherwise as
+// a blocked stream in the container. Pass `&mut writer` to keep using the
+// writer afterwards.
+pub fn encoder<'a, W: Write + 'a>(inner: W, algorithm: Algorithm, level: u32) -> io::Result<Box<dyn Encoder + 'a>> {
+    let codec = crate::codec::lookup(algorithm).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok(codec.stream_encoder(Box::new(inner), level))
+}
+
+// Decompresses a stream produced by `CompressWriter` or `crate::compress`.
+// Blocked streams are decoded one block at a time; single-shot streams are
+// decoded in one go on the first read.
+pub struct DecompressReader<R: Read> {
+    inner: R,
+    header: Option<Header>,
+    block: Vec<u8>,
+    pos: usize,
+    hasher: crc32fast::Hasher,
+    total: u64,
+    done: bool,
+}
+
+impl<R: Read> DecompressReader<R> {
+    pub fn new(inner: R) -> Self {
+        DecompressReader {
+            inner,
+            header: None,
+            block: Vec::new(),
+            pos: 0,
+            hasher: crc32fast::Hasher::new(),
+            total: 0,
+            done: false,
+        }
+    }
+
+    // Reads and returns the stream header; no payload is consumed.
+    pub fn header(&mut self) -> io::Result<Header> {
+        if let Some(header) = self.header {
+            return Ok(header);
+        }
+        let mut bytes = [0u8; format::HEADER_LEN];
+        self.inner.read_exact(&mut bytes).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid_data("Truncated header".to_string()),
+            _ => e,
+        })?;
+        let (header, _) = Header::parse(&bytes).map_err(invalid_data)?;
+        self.header = Some(header);
+        Ok(header)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.inner.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn next_block(&mut self) -> io::Result<()> {
+        let header = self.header()?;
+        self.block.clear();
+        self.pos = 0;
+
+        if !header.is_blocked() {
+            let mut payload = Vec::new();
+            self.inner.read_to_end(&mut payload)?;
+            self.block = crate::decode_payload(&header, &payload).map_err(invalid_data)?;
+            header.verify(&self.block).map_err(invalid_data)?;
+            self.done = true;
+            return Ok(());
+        }
+
+        let raw_len = self.read_u32()? as usize;
+        if raw_len == 0 {
+            let mut size = [0u8; 8];
+            self.inner.read_exact(&mut size)?;
+            let expected = Header {
+                original_size: u64::from_le_bytes(size),
+                checksum: self.read_u32()?,
+                ..header
+            };
+            if self.total != expected.original_size {
+                return Err(invalid_data(format!(
+                    "Size mismatch: expected {} bytes, got {}",
+                    expected.original_size, self.total
+                )));
+            }
+            let actual = self.hasher.clone().finalize();
+            if actual != expected.checksum {
+                return Err(invalid_data(format!(
+                    "Checksum mismatch: expected {:08x}, got {:08x}",
+                    expected.checksum, actual
+                )));
+            }
+            self.done = true;
+            return Ok(());
+        }
+
+        let packed_len = self.read_u32()? as usize;
+        if raw_len > MAX_BLOCK_SIZE || packed_len > 2 * MAX_BLOCK_SIZE {
+            return Err(invalid_data(format!("Block of {} bytes exceeds the size limit", raw_len)));
+        }
+        let checksum = if header.has_block_checksums() { Some(self.read_u32()?) } else { None };
+        let mut packed = vec![0u8; packed_len];
+        self.inner.read_exact(&mut packed)?;
+
+        // Errors name the block by the offset of its first byte in the
+        // uncompressed output.
+        let offset = self.total;
+        self.block = if header.has_stored_blocks() && packed_len == raw_len {
+            packed
+        } else {
+            crate::decode(&packed, header.algorithm, raw_len)
+                .map_err(|e| invalid_data(format!("Block at offset {}: {}", offset, e)))?
+        };
+        if self.block.len() != raw_len {
+            return Err(invalid_data(format!(
+                "Block at offset {}: size mismatch, expected {} bytes, got {}",
+                offset,
+                raw_len,
+                self.block.len()
+            )));
+        }
+        if let Some(expected) = checksum {
+            let actual = crc32fast::hash(&self.block);
+            if actual != expected {
+                return Err(invalid_data(format!(
+                    "Block at offset {}: checksum mismatch, expected {:08x}, got {:08x}",
+                    offset, expected, actual
+                )));
+            }
+        }
+        self.hasher.update(&self.block);
+        self.total += raw_len as u64;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecompressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.block.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_block()?;
+        }
+        let n = buf.len().min(self.block.len() - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -31,6 +347,208 @@ mod tests {
         assert_eq!(read_data, test_data);
     }
 
+    fn sample_data(len: usize) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| if i % 3 == 0 { b'a' } else { (i.wrapping_mul(2654435761) >> 27) as u8 })
+            .collect()
+    }
+
+    #[test]
+    fn test_compress_writer_round_trip() {
+        let data = sample_data(100_000);
+        for algorithm in [
+            Algorithm::Rle,
+            Algorithm::Lz77,
+            Algorithm::Lzh,
+            Algorithm::PackBits,
+            Algorithm::Deflate,
+            Algorithm::Zlib,
+            Algorithm::Gzip,
+            Algorithm::Auto,
+        ] {
+            let mut writer = CompressWriter::with_block_size(Vec::new(), algorithm, 6, 4096);
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let compressed = writer.finish().unwrap();
+
+            let mut reader = DecompressReader::new(Cursor::new(compressed));
+            assert_eq!(reader.header().unwrap().algorithm, algorithm);
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            assert_eq!(output, data);
+        }
+    }
+
+    #[test]
+    fn test_reader_matches_in_memory_decompress() {
+        let data = sample_data(10_000);
+        let mut writer = CompressWriter::new(Vec::new(), Algorithm::Lz77, 6);
+        writer.write_all(&data).unwrap();
+        let compressed = writer.finish().unwrap();
+        assert_eq!(crate::decompress(&compressed).unwrap(), data);
+
+        // Single-shot streams from `compress` are readable too.
+        let single = crate::compress(&data, Algorithm::Lz77).unwrap();
+        let mut output = Vec::new();
+        DecompressReader::new(Cursor::new(single)).read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_empty_compress_writer() {
+        let compressed = CompressWriter::new(Vec::new(), Algorithm::Lzh, 6).finish().unwrap();
+        let mut output = Vec::new();
+        DecompressReader::new(Cursor::new(compressed)).read_to_end(&mut output).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_drop_finishes_stream() {
+        let mut compressed = Vec::new();
+        {
+            let mut writer = CompressWriter::new(&mut compressed, Algorithm::Rle, 6);
+            writer.write_all(b"AAAABBBB").unwrap();
+        }
+        assert_eq!(crate::decompress(&compressed).unwrap(), b"AAAABBBB".to_vec());
+    }
+
+    // Fails the first write that would go past `fail_at` bytes, then
+    // accepts everything again.
+    struct FailOnce {
+        data: Vec<u8>,
+        fail_at: usize,
+        failed: bool,
+    }
+
+    impl Write for FailOnce {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.failed && self.data.len() + buf.len() > self.fail_at {
+                self.failed = true;
+                return Err(io::Error::other("disk full"));
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_finish_writes_no_second_trailer() {
+        let mut sink = FailOnce { data: Vec::new(), fail_at: format::HEADER_LEN, failed: false };
+        let mut writer = CompressWriter::new(&mut sink, Algorithm::Rle, 6);
+        writer.write_all(b"AAAABBBB").unwrap();
+        assert!(writer.finish().is_err());
+        assert_eq!(sink.data.len(), format::HEADER_LEN);
+
+        let mut sink = FailOnce { data: Vec::new(), fail_at: format::HEADER_LEN, failed: false };
+        {
+            let mut writer = CompressWriter::new(&mut sink, Algorithm::Rle, 6);
+            writer.write_all(b"AAAABBBB").unwrap();
+        }
+        assert_eq!(sink.data.len(), format::HEADER_LEN);
+    }
+
+    #[test]
+    fn test_failed_write_consumes_nothing() {
+        let mut sink = FailOnce { data: Vec::new(), fail_at: format::HEADER_LEN, failed: false };
+        let data = sample_data(3000);
+        let mut writer = CompressWriter::with_block_size(&mut sink, Algorithm::Lz77, 6, 1024);
+        assert_eq!(writer.write(&data[..1024]).unwrap(), 1024);
+        assert!(writer.write(&data[1024..]).is_err());
+        // Retrying the same data must not duplicate it
+        writer.write_all(&data[1024..]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(crate::decompress(&sink.data).unwrap(), data);
+    }
+
+    #[test]
+    fn test_reader_detects_corruption() {
+        let data = sample_data(50_000);
+        let mut writer = CompressWriter::with_block_size(Vec::new(), Algorithm::PackBits, 6, 8192);
+        writer.write_all(&data).unwrap();
+        let mut compressed = writer.finish().unwrap();
+        let len = compressed.len();
+        compressed[len - 1] ^= 0xff;
+
+        let mut output = Vec::new();
+        let err = DecompressReader::new(Cursor::new(compressed)).read_to_end(&mut output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_reader_reports_corrupt_block_offset() {
+        // PackBits stores this data as literal packets, so a flipped byte
+        // still decodes and only the block checksum can catch it.
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = CompressWriter::with_block_size(Vec::new(), Algorithm::PackBits, 6, 1000);
+        writer.write_all(&data).unwrap();
+        let mut compressed = writer.finish().unwrap();
+
+        // Skip the header and the first block to land in the second one.
+        let len_field = format::HEADER_LEN + 4;
+        let first_packed = u32::from_le_bytes(compressed[len_field..len_field + 4].try_into().unwrap()) as usize;
+        let second = format::HEADER_LEN + 12 + first_packed;
+        compressed[second + 12 + 10] ^= 0x01;
+
+        let mut output = Vec::new();
+        let err = DecompressReader::new(Cursor::new(compressed)).read_to_end(&mut output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Block at offset 1000"), "{}", err);
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn test_reader_accepts_blocks_without_checksums() {
+        let data = sample_data(5000);
+        let mut stream = Header { flags: format::FLAG_BLOCKS, ..Header::blocked(Algorithm::Rle) }
+            .to_bytes()
+            .to_vec();
+        let packed = crate::encode(&data, Algorithm::Rle, 6).unwrap();
+        stream.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        stream.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+        stream.extend_from_slice(&packed);
+        stream.extend_from_slice(&0u32.to_le_bytes());
+        stream.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        stream.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+
+        let mut output = Vec::new();
+        DecompressReader::new(Cursor::new(stream)).read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_reader_limits_blocks_to_raw_length() {
+        // Small blocks claiming 10 bytes that decode to far more
+        for algorithm in [Algorithm::Lz77, Algorithm::Lzh, Algorithm::Deflate] {
+            let mut stream = Header::blocked(algorithm).to_bytes().to_vec();
+            let packed = crate::encode(&vec![0u8; 1 << 20], algorithm, 6).unwrap();
+            stream.extend_from_slice(&10u32.to_le_bytes());
+            stream.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+            stream.extend_from_slice(&0u32.to_le_bytes());
+            stream.extend_from_slice(&packed);
+
+            let mut output = Vec::new();
+            let err = DecompressReader::new(Cursor::new(stream)).read_to_end(&mut output).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("exceeds the expected 10 bytes"), "{}: {}", algorithm.name(), err);
+        }
+    }
+
+    #[test]
+    fn test_reader_rejects_truncated_stream() {
+        let mut writer = CompressWriter::with_block_size(Vec::new(), Algorithm::Lz77, 6, 1024);
+        writer.write_all(&sample_data(10_000)).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        let mut output = Vec::new();
+        let truncated = Cursor::new(&compressed[..compressed.len() / 2]);
+        assert!(DecompressReader::new(truncated).read_to_end(&mut output).is_err());
+    }
+
     #[test]
     fn test_empty_stream() {
         let empty_data: &[u8] = &[];
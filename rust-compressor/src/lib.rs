@@ -1,121 +1,395 @@
 pub mod algorithms {
     pub mod rle;
     pub mod lz77;
+    pub mod hash_chain;
+    pub mod huffman;
+    pub mod lzh;
+    pub mod packbits;
+    pub mod deflate;
 }
 
 pub mod io {
     pub mod file;
+    pub mod metadata;
     pub mod stream;
 }
 
+pub mod archive;
 pub mod cli;
+pub mod codec;
 pub mod detect;
+pub mod format;
+pub mod tarball;
 
-pub fn compress(data: &[u8], use_rle: bool) -> Result<Vec<u8>, String> {
-    if use_rle {
-        algorithms::rle::compress_rle(data)
-    } else {
-        algorithms::lz77::compress_lz77(data)
-            .map(|compressed| compressed.iter().map(|&x| x as u8).collect())
+pub use algorithms::lz77::{DEFAULT_LEVEL, MAX_LEVEL, MIN_LEVEL};
+pub(crate) use algorithms::lz77::check_level;
+pub use archive::{compress_multiple_files, decompress_multiple_files};
+pub use codec::Codec;
+pub use detect::{Signature, SIGNATURES};
+pub use format::Algorithm;
+
+pub(crate) fn encode(data: &[u8], algorithm: Algorithm, level: u32) -> Result<Vec<u8>, String> {
+    codec::lookup(algorithm)?.compress(data, level)
+}
+
+// Fails if the output would be larger than `limit` bytes.
+pub(crate) fn decode(data: &[u8], algorithm: Algorithm, limit: usize) -> Result<Vec<u8>, String> {
+    let output = codec::lookup(algorithm)?.decompress(data, limit)?;
+    if output.len() > limit {
+        return Err(format!("Output exceeds the expected {} bytes", limit));
+    }
+    Ok(output)
+}
+
+// Decodes the payload of a single-shot (not blocked) stream.
+pub(crate) fn decode_payload(header: &format::Header, payload: &[u8]) -> Result<Vec<u8>, String> {
+    if header.version == 1 && header.algorithm == Algorithm::Lz77 {
+        return algorithms::lz77::decompress_lz77_legacy(payload);
+    }
+    decode(payload, header.algorithm, header.size_limit())
+}
+
+pub fn compress(data: &[u8], algorithm: Algorithm) -> Result<Vec<u8>, String> {
+    compress_with_level(data, algorithm, DEFAULT_LEVEL)
+}
+
+// `level` trades speed for ratio, from MIN_LEVEL (fastest) to MAX_LEVEL
+// (smallest output). Algorithms without tuning knobs ignore it.
+pub fn compress_with_level(data: &[u8], algorithm: Algorithm, level: u32) -> Result<Vec<u8>, String> {
+    check_level(level)?;
+    if algorithm.is_standard_stream() {
+        return encode(data, algorithm, level);
+    }
+
+    // Data the codec doesn't shrink is stored as is instead
+    let mut payload = encode(data, algorithm, level)?;
+    let mut algorithm = algorithm;
+    if payload.len() >= data.len() {
+        payload = data.to_vec();
+        algorithm = Algorithm::Stored;
+    }
+    let header = format::Header::new(algorithm, data);
+
+    let mut output = Vec::with_capacity(format::HEADER_LEN + payload.len());
+    output.extend_from_slice(&header.to_bytes());
+    output.extend_from_slice(&payload);
+    Ok(output)
+}
+
+// Also accepts standard streams such as gzip and zlib, including ones
+// written by other tools.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if !format::has_magic(data) {
+        if let Some(codec) = codec::sniff(data) {
+            return codec.decompress(data, usize::MAX);
+        }
+    }
+    let (header, payload) = format::Header::parse(data)?;
+    if header.is_blocked() {
+        let mut output = Vec::new();
+        std::io::Read::read_to_end(&mut io::stream::DecompressReader::new(data), &mut output)
+            .map_err(|e| e.to_string())?;
+        return Ok(output);
+    }
+    let output = decode_payload(&header, payload)?;
+    header.verify(&output)?;
+    Ok(output)
+}
+
+// Compresses everything from `reader` into `writer` as a blocked stream,
+// holding at most one block in memory.
+pub fn compress_stream<R: std::io::Read, W: std::io::Write>(
+    mut reader: R,
+    writer: W,
+    algorithm: Algorithm,
+    level: u32,
+) -> std::io::Result<W> {
+    check_level(level).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut writer = writer;
+    let mut encoder = io::stream::encoder(&mut writer, algorithm, level)?;
+    std::io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?;
+    Ok(writer)
+}
+
+pub fn decompress_stream<R: std::io::Read, W: std::io::Write>(reader: R, mut writer: W) -> std::io::Result<W> {
+    std::io::copy(&mut decoder(reader)?, &mut writer)?;
+    Ok(writer)
+}
+
+// Wraps `reader` in a decoder for whatever it starts with: a standard stream
+// such as gzip or zlib, or this crate's container.
+pub fn decoder<'a, R: std::io::Read + 'a>(reader: R) -> std::io::Result<Box<dyn std::io::Read + 'a>> {
+    let reader = peek(reader, codec::SNIFF_LEN)?;
+    let prefix = reader.get_ref().0.get_ref();
+    if format::has_magic(prefix) {
+        return Ok(Box::new(io::stream::DecompressReader::new(reader)));
     }
+    Ok(match codec::sniff(prefix) {
+        Some(codec) => codec.stream_decoder(Box::new(reader)),
+        None => Box::new(io::stream::DecompressReader::new(reader)),
+    })
 }
 
-pub fn decompress(data: &[u8], use_rle: bool) -> Result<Vec<u8>, String> {
-    if use_rle {
-        algorithms::rle::decompress_rle(data)
-    } else {
-        algorithms::lz77::decompress_lz77(data)
+// Identifies the algorithm of a compressed stream from its first
+// `format::HEADER_LEN` bytes.
+pub fn stream_algorithm(prefix: &[u8]) -> Option<Algorithm> {
+    if !format::has_magic(prefix) {
+        if let Some(codec) = codec::sniff(prefix) {
+            return Algorithm::from_id(codec.id());
+        }
     }
+    format::Header::parse(prefix).ok().map(|(header, _)| header.algorithm)
 }
 
-pub fn compress_file(input_path: &str, output_path: &str, use_rle: bool) -> std::io::Result<()> {
-    let data = io::file::read_file(input_path)?;
-    let compressed = compress(&data, use_rle)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    io::file::write_file(output_path, &compressed)
+// Reads up to `len` bytes from the start of `reader` into a buffer and
+// chains it back in front, so the caller can look at the first bytes and
+// still read the whole input.
+pub(crate) fn peek<R: std::io::Read>(mut reader: R, len: usize) -> std::io::Result<std::io::Chain<std::io::Cursor<Vec<u8>>, R>> {
+    use std::io::Read;
+
+    let mut prefix = Vec::with_capacity(len);
+    (&mut reader).take(len as u64).read_to_end(&mut prefix)?;
+    Ok(std::io::Cursor::new(prefix).chain(reader))
 }
 
-pub fn decompress_file(input_path: &str, output_path: &str, use_rle: bool) -> std::io::Result<()> {
-    let data = io::file::read_file(input_path)?;
-    let decompressed = decompress(&data, use_rle)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    io::file::write_file(output_path, &decompressed)
+// Decompresses a single stream or every entry of an archive without keeping
+// the output, checking sizes and checksums. `name` labels the result for a
+// single stream. Only I/O errors on `reader` itself are returned as `Err`;
+// corrupt data shows up as failed checks.
+pub fn verify<R: std::io::Read>(reader: R, name: &str) -> std::io::Result<Vec<archive::EntryCheck>> {
+    let reader = peek(reader, archive::MAGIC.len())?;
+    if archive::has_magic(reader.get_ref().0.get_ref()) {
+        return Ok(match archive::ArchiveReader::new(reader) {
+            Ok(entries) => archive::verify_entries(entries),
+            Err(e) => vec![archive::EntryCheck { path: name.to_string(), size: 0, error: Some(e.to_string()) }],
+        });
+    }
+
+    let mut sink = archive::ChecksumSink::default();
+    let error = std::io::copy(&mut decoder(reader)?, &mut sink).err().map(|e| e.to_string());
+    Ok(vec![archive::EntryCheck { path: name.to_string(), size: sink.size(), error }])
 }
 
-pub fn compress_multiple_files(input_paths: &[String], output_path: &str, use_rle: bool) -> std::io::Result<()> {
+// Decodes a headerless stream written before the container format existed.
+// The caller has to know which algorithm produced it.
+pub fn decompress_legacy(data: &[u8], algorithm: Algorithm) -> Result<Vec<u8>, String> {
+    match algorithm {
+        Algorithm::Rle => algorithms::rle::decompress_rle(data),
+        Algorithm::Lz77 => algorithms::lz77::decompress_lz77_legacy(data),
+        other => Err(format!("{} has no legacy headerless format", other.name())),
+    }
+}
+
+pub fn compress_file(input_path: &str, output_path: &str, algorithm: Algorithm, level: u32) -> std::io::Result<()> {
     use std::fs::File;
-    use std::io::Write;
-    use std::path::Path;
-    
-    // Create output file
-    let mut output_file = File::create(output_path)?;
-    
-    // Write number of files as first 4 bytes
-    output_file.write_all(&(input_paths.len() as u32).to_le_bytes())?;
-    
-    for input_path in input_paths {
-        // Read input file
-        let data = io::file::read_file(input_path)?;
-        
-        // Compress the data
-        let compressed = compress(&data, use_rle)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            
-        // Write filename length and filename
-        let filename = Path::new(input_path).file_name().unwrap().to_str().unwrap();
-        output_file.write_all(&(filename.len() as u32).to_le_bytes())?;
-        output_file.write_all(filename.as_bytes())?;
-        
-        // Write compressed data length and data
-        output_file.write_all(&(compressed.len() as u32).to_le_bytes())?;
-        output_file.write_all(&compressed)?;
-    }
-    
-    Ok(())
-}
-
-pub fn decompress_multiple_files(input_path: &str, output_dir: &str, use_rle: bool) -> std::io::Result<()> {
+    use std::io::{BufReader, BufWriter, Write};
+
+    let input = BufReader::new(File::open(input_path)?);
+    let output = BufWriter::new(File::create(output_path)?);
+    compress_stream(input, output, algorithm, level)?.flush()
+}
+
+pub fn decompress_file(input_path: &str, output_path: &str) -> std::io::Result<()> {
     use std::fs::File;
-    use std::io::Read;
-    use std::path::Path;
-    
-    // Create output directory if it doesn't exist
-    std::fs::create_dir_all(output_dir)?;
-    
-    // Open input file
-    let mut input_file = File::open(input_path)?;
-    
-    // Read number of files
-    let mut num_files_buf = [0u8; 4];
-    input_file.read_exact(&mut num_files_buf)?;
-    let num_files = u32::from_le_bytes(num_files_buf) as usize;
-    
-    for _ in 0..num_files {
-        // Read filename length and filename
-        let mut filename_len_buf = [0u8; 4];
-        input_file.read_exact(&mut filename_len_buf)?;
-        let filename_len = u32::from_le_bytes(filename_len_buf) as usize;
-        
-        let mut filename_buf = vec![0u8; filename_len];
-        input_file.read_exact(&mut filename_buf)?;
-        let filename = String::from_utf8(filename_buf).unwrap();
-        
-        // Read compressed data length and data
-        let mut data_len_buf = [0u8; 4];
-        input_file.read_exact(&mut data_len_buf)?;
-        let data_len = u32::from_le_bytes(data_len_buf) as usize;
-        
-        let mut compressed_data = vec![0u8; data_len];
-        input_file.read_exact(&mut compressed_data)?;
-        
-        // Decompress the data
-        let decompressed = decompress(&compressed_data, use_rle)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            
-        // Write decompressed data to output file
-        let output_path = Path::new(output_dir).join(filename);
-        io::file::write_file(output_path.to_str().unwrap(), &decompressed)?;
-    }
-    
-    Ok(())
+    use std::io::{BufReader, BufWriter, Write};
+
+    let input = BufReader::new(File::open(input_path)?);
+    let output = BufWriter::new(File::create(output_path)?);
+    decompress_stream(input, output)?.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_with_header() {
+        let input = [vec![b'A'; 200], vec![b'B'; 200], b"hello hello hello".to_vec()].concat();
+        for algorithm in [Algorithm::Rle, Algorithm::Lz77, Algorithm::Lzh, Algorithm::PackBits, Algorithm::Deflate] {
+            let compressed = compress(&input, algorithm).unwrap();
+            assert!(format::has_magic(&compressed));
+            assert_eq!(compressed[5], algorithm.id());
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_stored_when_compression_expands() {
+        // RLE doubles data without runs
+        let input = b"AAABBBCCCCCDDDDE hello hello hello";
+        let compressed = compress(input, Algorithm::Rle).unwrap();
+        assert_eq!(compressed[5], Algorithm::Stored.id());
+        assert_eq!(compressed.len(), format::HEADER_LEN + input.len());
+        assert_eq!(decompress(&compressed).unwrap(), input);
+
+        // In a blocked stream only the blocks that don't shrink are stored
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let noise: Vec<u8> = (0..8192)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect();
+        let input = [noise.clone(), vec![0; 8192]].concat();
+        for algorithm in [Algorithm::Rle, Algorithm::Lz77, Algorithm::Auto] {
+            let mut writer = io::stream::CompressWriter::with_block_size(Vec::new(), algorithm, 6, 8192);
+            std::io::Write::write_all(&mut writer, &input).unwrap();
+            let compressed = writer.finish().unwrap();
+            let packed_len = u32::from_le_bytes(compressed[format::HEADER_LEN + 4..format::HEADER_LEN + 8].try_into().unwrap());
+            assert_eq!(packed_len, 8192, "{}", algorithm.name());
+            assert!(compressed.len() < input.len() / 2 + 256, "{}", algorithm.name());
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_gzip_and_zlib_are_standard_streams() {
+        use std::io::Read;
+
+        let input = b"interoperable interoperable interoperable".repeat(50);
+        let gzip = compress(&input, Algorithm::Gzip).unwrap();
+        let mut output = Vec::new();
+        flate2::read::GzDecoder::new(&gzip[..]).read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+        assert_eq!(decompress(&gzip).unwrap(), input);
+
+        let zlib = compress_stream(&input[..], Vec::new(), Algorithm::Zlib, 9).unwrap();
+        assert!(algorithms::deflate::is_zlib(&zlib));
+        assert_eq!(decompress(&zlib).unwrap(), input);
+        assert_eq!(decompress_stream(&zlib[..], Vec::new()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_decompress_foreign_gzip() {
+        use std::io::Write;
+
+        // gzip(1) stores the file name and modification time.
+        let mut encoder = flate2::GzBuilder::new()
+            .filename("notes.txt")
+            .mtime(1_700_000_000)
+            .write(Vec::new(), flate2::Compression::best());
+        encoder.write_all(b"written elsewhere").unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        assert_eq!(decompress_stream(&gzip[..], Vec::new()).unwrap(), b"written elsewhere");
+        let checks = verify(&gzip[..], "notes.txt.gz").unwrap();
+        assert!(checks[0].passed());
+        assert_eq!(checks[0].size, 17);
+
+        let mut corrupted = gzip.clone();
+        let crc = corrupted.len() - 8;
+        corrupted[crc] ^= 0xff;
+        assert!(decompress_stream(&corrupted[..], Vec::new()).is_err());
+        assert!(!verify(&corrupted[..], "bad.gz").unwrap()[0].passed());
+    }
+
+    #[test]
+    fn test_auto_picks_codec_per_block() {
+        let text = b"the cat sat on the mat, the cat sat on the hat. ".repeat(200);
+        let mut runs = Vec::new();
+        for i in 0..40u8 {
+            runs.extend(std::iter::repeat_n(i, 250));
+        }
+
+        let compressed = compress(&text, Algorithm::Auto).unwrap();
+        assert_eq!(compressed[5], Algorithm::Auto.id());
+        assert_eq!(decompress(&compressed).unwrap(), text);
+
+        // One block of text followed by one of runs; each block records the
+        // codec that won its trial, right after the block's checksum.
+        let input = [text.clone(), runs.clone()].concat();
+        let mut writer = io::stream::CompressWriter::with_block_size(Vec::new(), Algorithm::Auto, 6, text.len());
+        std::io::Write::write_all(&mut writer, &input).unwrap();
+        let compressed = writer.finish().unwrap();
+        let first = format::HEADER_LEN + 12;
+        let packed_len = u32::from_le_bytes(compressed[format::HEADER_LEN + 4..format::HEADER_LEN + 8].try_into().unwrap()) as usize;
+        let second = first + packed_len + 12;
+        let codecs = [compressed[first], compressed[second]].map(|id| Algorithm::from_id(id).unwrap());
+        assert_ne!(codecs[0], codecs[1], "{:?}", codecs);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+
+        assert_eq!(decompress(&compress(b"", Algorithm::Auto).unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_auto_rejects_bad_codec_id() {
+        let mut compressed = compress(b"some data some data", Algorithm::Auto).unwrap();
+        for id in [0, Algorithm::Auto.id(), 0xee] {
+            compressed[format::HEADER_LEN] = id;
+            assert!(decompress(&compressed).unwrap_err().contains("auto payload"));
+        }
+    }
+
+    #[test]
+    fn test_compress_with_level() {
+        let input = b"the cat sat on the mat, the cat sat on the hat".repeat(10);
+        for level in MIN_LEVEL..=MAX_LEVEL {
+            let compressed = compress_with_level(&input, Algorithm::Lz77, level).unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+        assert!(compress_with_level(&input, Algorithm::Rle, 0).is_err());
+    }
+
+    #[test]
+    fn test_stream_functions_round_trip() {
+        let input = b"streaming streaming streaming data".repeat(1000);
+        let compressed = compress_stream(&input[..], Vec::new(), Algorithm::Lzh, 6).unwrap();
+        assert!(compressed.len() < input.len() / 10);
+        let output = decompress_stream(&compressed[..], Vec::new()).unwrap();
+        assert_eq!(output, input);
+        assert!(compress_stream(&input[..], Vec::new(), Algorithm::Lzh, 10).is_err());
+    }
+
+    #[test]
+    fn test_verify_single_stream() {
+        let input = b"verify me ".repeat(100);
+        let compressed = compress_stream(&input[..], Vec::new(), Algorithm::Lz77, 6).unwrap();
+        let checks = verify(&compressed[..], "data.rz").unwrap();
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].passed());
+        assert_eq!(checks[0].size, input.len() as u64);
+
+        let mut corrupted = compressed.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        assert!(!verify(&corrupted[..], "data.rz").unwrap()[0].passed());
+        assert!(!verify(&b"no"[..], "tiny").unwrap()[0].passed());
+    }
+
+    #[test]
+    fn test_decompress_rejects_headerless_data() {
+        let raw = algorithms::rle::compress_rle(b"AAAB").unwrap();
+        assert!(decompress(&raw).is_err());
+        assert_eq!(decompress_legacy(&raw, Algorithm::Rle).unwrap(), b"AAAB".to_vec());
+    }
+
+    #[test]
+    fn test_decompress_version_1_lz77() {
+        // Written by the first container release, before LZ77 switched to
+        // LEB128 tokens: "ab" as literals, then a match of 4 at offset 2
+        let mut stream = format::Header::new(Algorithm::Lz77, b"ababab").to_bytes().to_vec();
+        stream[4] = 1;
+        stream.extend_from_slice(&[0, b'a', 0, b'b', 1, 2, 4]);
+        assert_eq!(decompress(&stream).unwrap(), b"ababab".to_vec());
+
+        let mut reader = io::stream::DecompressReader::new(&stream[..]);
+        let mut output = Vec::new();
+        std::io::Read::read_to_end(&mut reader, &mut output).unwrap();
+        assert_eq!(output, b"ababab".to_vec());
+
+        // Version 1 had no blocks
+        stream[6] = format::FLAG_BLOCKS;
+        assert!(decompress(&stream).is_err());
+    }
+
+    #[test]
+    fn test_decompress_detects_corrupted_payload() {
+        let mut compressed = compress(b"AAAABBBB", Algorithm::Rle).unwrap();
+        let last = compressed.len() - 1;
+        compressed[last] = 3;
+        assert!(decompress(&compressed).is_err());
+    }
 }
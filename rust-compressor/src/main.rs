@@ -1,7 +1,10 @@
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use clap::{Parser, Subcommand};
+use rust_compressor::archive::{EntryKind, ExtractOptions, OverwritePolicy};
+use rust_compressor::io::metadata::RestoreOptions;
+use rust_compressor::Algorithm;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -13,37 +16,139 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Compress {
-        #[arg(value_parser)]
-        inputs: Vec<PathBuf>,
-        
-        #[arg(value_parser)] 
-        output: PathBuf,
+        /// Input files followed by the output path. `-` means stdin or
+        /// stdout; a single path is compressed to stdout
+        #[arg(value_parser, required = true, value_name = "INPUTS... OUTPUT")]
+        paths: Vec<PathBuf>,
 
         #[arg(long)]
         rle: bool,
 
         #[arg(long)]
         lz: bool,
+
+        #[arg(long, value_parser = rust_compressor::cli::algorithm_parser(), conflicts_with_all = ["rle", "lz"])]
+        algo: Option<Algorithm>,
+
+        /// Compression level: 1 is fastest, 9 gives the smallest output
+        #[arg(long, default_value_t = rust_compressor::DEFAULT_LEVEL,
+              value_parser = clap::value_parser!(u32)
+                  .range(rust_compressor::MIN_LEVEL as i64..=rust_compressor::MAX_LEVEL as i64))]
+        level: u32,
+
+        /// Archive the inputs as a standard tar stream compressed with the
+        /// chosen algorithm, e.g. `--tar --algo gzip` for a .tar.gz
+        #[arg(long)]
+        tar: bool,
     },
     Decompress {
+        /// Compressed file, or `-` for stdin
         #[arg(value_parser)]
         input: PathBuf,
-        
+
+        /// Output file or directory; `-` or omitted means stdout. A
+        /// compressed tar stream is extracted when this is an existing
+        /// directory
         #[arg(value_parser)]
-        output: PathBuf,
+        output: Option<PathBuf>,
 
+        /// Read a headerless file written by older versions; with an
+        /// existing output directory, their multi-file output
         #[arg(long)]
+        legacy: bool,
+
+        #[arg(long, requires = "legacy")]
         rle: bool,
 
-        #[arg(long)]
+        #[arg(long, requires = "legacy")]
         lz: bool,
+
+        /// Replace existing files when extracting an archive
+        #[arg(long, conflicts_with_all = ["skip_existing", "keep_newer"])]
+        overwrite: bool,
+
+        /// Leave existing files untouched when extracting an archive
+        #[arg(long, conflicts_with = "keep_newer")]
+        skip_existing: bool,
+
+        /// Only replace existing files that are older than the archived ones
+        #[arg(long)]
+        keep_newer: bool,
+
+        #[command(flatten)]
+        restore: RestoreArgs,
+    },
+    /// Check that a compressed file or archive decompresses cleanly,
+    /// without writing anything to disk
+    Test {
+        /// Compressed file or archive, or `-` for stdin
+        #[arg(value_parser)]
+        input: PathBuf,
+    },
+    /// Extract selected entries of an archive, seeking to each through
+    /// the archive index
+    Extract {
+        #[arg(value_parser)]
+        archive: PathBuf,
+
+        /// Paths of the entries as shown by `list`
+        #[arg(required = true)]
+        entries: Vec<String>,
+
+        /// Directory to extract into
+        #[arg(short = 'C', long, default_value = ".")]
+        directory: PathBuf,
+
+        /// Write the entries to stdout instead of extracting them
+        #[arg(long, conflicts_with = "directory")]
+        stdout: bool,
+
+        /// Replace existing files
+        #[arg(long)]
+        overwrite: bool,
+
+        #[command(flatten)]
+        restore: RestoreArgs,
+    },
+    /// Show the entries of an archive without extracting them
+    List {
+        #[arg(value_parser)]
+        archive: PathBuf,
+
+        /// Print the entries as a JSON array
+        #[arg(long)]
+        json: bool,
     },
 }
 
+#[derive(clap::Args)]
+struct RestoreArgs {
+    /// Extract files as the current user instead of the archived owner.
+    /// This is the default unless running as root
+    #[arg(long)]
+    no_same_owner: bool,
+
+    /// Apply the archived permission bits as is, including setuid, setgid
+    /// and sticky, instead of masking them with the umask
+    #[arg(long)]
+    preserve_permissions: bool,
+}
+
+impl RestoreArgs {
+    fn options(&self) -> RestoreOptions {
+        let defaults = RestoreOptions::default();
+        RestoreOptions {
+            same_owner: defaults.same_owner && !self.no_same_owner,
+            preserve_permissions: self.preserve_permissions,
+        }
+    }
+}
+
 #[derive(Debug)]
 enum CompressionError {
     Io(io::Error),
     InvalidAlgorithm(String),
+    InvalidArgument(String),
     Compression(String),
 }
 
@@ -52,6 +157,7 @@ impl std::fmt::Display for CompressionError {
         match self {
             CompressionError::Io(e) => write!(f, "IO error: {}", e),
             CompressionError::InvalidAlgorithm(msg) => write!(f, "Invalid algorithm: {}", msg),
+            CompressionError::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
             CompressionError::Compression(msg) => write!(f, "Compression error: {}", msg),
         }
     }
@@ -65,69 +171,330 @@ impl From<io::Error> for CompressionError {
     }
 }
 
-fn determine_algorithm(input: &PathBuf, rle: bool, lz: bool) -> Result<bool, CompressionError> {
+// The library's path-based APIs take strings
+fn path_str(path: &Path) -> Result<&str, CompressionError> {
+    path.to_str()
+        .ok_or_else(|| CompressionError::InvalidArgument(format!("{} is not valid UTF-8", path.display())))
+}
+
+fn explicit_algorithm(algo: Option<Algorithm>, rle: bool, lz: bool) -> Result<Option<Algorithm>, CompressionError> {
+    if algo.is_some() {
+        return Ok(algo);
+    }
     if rle && lz {
         return Err(CompressionError::InvalidAlgorithm("Cannot specify both --rle and --lz".to_string()));
     }
     if rle {
-        Ok(true) // Use RLE
+        Ok(Some(Algorithm::Rle))
     } else if lz {
-        Ok(false) // Use LZ77
+        Ok(Some(Algorithm::Lz77))
+    } else {
+        Ok(None)
+    }
+}
+
+fn determine_algorithm(input: &Path, algo: Option<Algorithm>, rle: bool, lz: bool) -> Result<Algorithm, CompressionError> {
+    if let Some(algorithm) = explicit_algorithm(algo, rle, lz)? {
+        return Ok(algorithm);
+    }
+    // Auto-detect from a sample of the content. Stdin can't be rewound
+    // and has no name, so it always gets LZ77.
+    if is_stdio(input) {
+        return Ok(Algorithm::Lz77);
+    }
+    Ok(rust_compressor::detect::detect_file(input)?.algorithm)
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        Ok(Box::new(io::stdin().lock()))
     } else {
-        // Auto-detect based on file type
-        let algo = rust_compressor::detect::detect_best_algorithm(input.to_str().unwrap());
-        Ok(algo == "rle")
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+fn create_output(path: Option<&Path>) -> io::Result<Box<dyn Write>> {
+    match path {
+        Some(path) if !is_stdio(path) => Ok(Box::new(BufWriter::new(File::create(path)?))),
+        _ => Ok(Box::new(BufWriter::new(io::stdout().lock()))),
     }
 }
 
 fn main() -> Result<(), CompressionError> {
-    let cli = Cli::parse();
+    match run(Cli::parse()) {
+        // The reading end of a pipeline went away (e.g. `| head`); that's
+        // not an error worth reporting.
+        Err(CompressionError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
 
+fn run(cli: Cli) -> Result<(), CompressionError> {
     match &cli.command {
-        Commands::Compress { inputs, output, rle, lz } => {
-            if inputs.len() == 1 {
-                // Single file compression
-                let mut input_file = File::open(&inputs[0])?;
-                let mut data = Vec::new();
-                input_file.read_to_end(&mut data)?;
-                
-                let use_rle = determine_algorithm(&inputs[0], *rle, *lz)?;
-                let compressed = rust_compressor::compress(&data, use_rle)
-                    .map_err(|e| CompressionError::Compression(e))?;
+        Commands::Compress { paths, rle, lz, algo, level, tar } => {
+            let (inputs, output) = match paths.split_last() {
+                Some((output, inputs)) if !inputs.is_empty() => (inputs, Some(output.as_path())),
+                _ => (&paths[..], None),
+            };
 
-                let mut output_file = File::create(output)?;
-                output_file.write_all(&compressed)?;
+            if inputs.len() == 1 && !inputs[0].is_dir() && !*tar {
+                // Single stream compression, processed block by block
+                let algorithm = determine_algorithm(&inputs[0], *algo, *rle, *lz)?;
+                let reader = open_input(&inputs[0])?;
+                let writer = create_output(output)?;
+                rust_compressor::compress_stream(reader, writer, algorithm, *level)?.flush()?;
             } else {
-                // Multiple file compression
-                let input_paths: Vec<String> = inputs.iter()
-                    .map(|p| p.to_str().unwrap().to_string())
-                    .collect();
+                // Tar streams are written front to back, so only they can go
+                // to stdout.
+                if (!*tar && output.is_none_or(is_stdio)) || inputs.iter().any(|p| is_stdio(p)) {
+                    return Err(CompressionError::InvalidArgument(
+                        "stdin/stdout can only be used when compressing a single stream".to_string(),
+                    ));
+                }
+
+                // Multiple files or directories go into an archive
+                let input_paths = inputs.iter()
+                    .map(|p| path_str(p).map(str::to_string))
+                    .collect::<Result<Vec<_>, _>>()?;
                 
                 // For multiple files, use the algorithm specified or default to LZ77
-                let use_rle = if *rle { true } else if *lz { false } else { false };
-                rust_compressor::compress_multiple_files(&input_paths, output.to_str().unwrap(), use_rle)?;
+                let algorithm = explicit_algorithm(*algo, *rle, *lz)?.unwrap_or(Algorithm::Lz77);
+                if *tar {
+                    let entries = rust_compressor::archive::collect_entries(&input_paths)?;
+                    let writer = create_output(output)?;
+                    rust_compressor::tarball::write_tar(&entries, writer, algorithm, *level)?.flush()?;
+                } else {
+                    rust_compressor::compress_multiple_files(&input_paths, path_str(output.unwrap())?, algorithm, *level)?;
+                }
             }
         }
-        Commands::Decompress { input, output, rle, lz } => {
-            let use_rle = determine_algorithm(input, *rle, *lz)?;
-            
-            if output.is_dir() {
-                // Multiple file decompression
-                rust_compressor::decompress_multiple_files(input.to_str().unwrap(), output.to_str().unwrap(), use_rle)?;
+        Commands::Decompress { input, output, legacy, rle, lz, overwrite, skip_existing, keep_newer, restore } => {
+            let output = output.as_deref();
+            let policy = if *overwrite {
+                OverwritePolicy::Overwrite
+            } else if *skip_existing {
+                OverwritePolicy::Skip
+            } else if *keep_newer {
+                OverwritePolicy::KeepNewer
             } else {
-                // Single file decompression
-                let mut input_file = File::open(input)?;
-                let mut data = Vec::new();
-                input_file.read_to_end(&mut data)?;
+                OverwritePolicy::Error
+            };
+            let options = ExtractOptions { overwrite: policy, restore: restore.options() };
+
+            if *legacy {
+                // Headerless files predate PackBits, so RLE means the
+                // original pair encoding here. The content is already
+                // compressed, so only the name can hint at the algorithm.
+                let algorithm = explicit_algorithm(None, *rle, *lz)?
+                    .or_else(|| input.to_str().and_then(rust_compressor::detect::extension_hint))
+                    .unwrap_or(Algorithm::Lz77);
+                let algorithm = match algorithm {
+                    Algorithm::PackBits => Algorithm::Rle,
+                    other => other,
+                };
 
-                let decompressed = rust_compressor::decompress(&data, use_rle)
-                    .map_err(|e| CompressionError::Compression(e))?;
+                // Like the old tool, an existing output directory means the
+                // input holds several files
+                if let Some(dir) = output.filter(|dir| !is_stdio(dir) && dir.is_dir()) {
+                    rust_compressor::archive::extract_legacy_files(open_input(input)?, dir, algorithm, &options)?;
+                } else {
+                    let mut data = Vec::new();
+                    open_input(input)?.read_to_end(&mut data)?;
+                    let decompressed = rust_compressor::decompress_legacy(&data, algorithm)
+                        .map_err(CompressionError::Compression)?;
 
-                let mut output_file = File::create(output)?;
-                output_file.write_all(&decompressed)?;
+                    let mut writer = create_output(output)?;
+                    writer.write_all(&decompressed)?;
+                    writer.flush()?;
+                }
+            } else if !is_stdio(input) && rust_compressor::archive::is_archive(input)? {
+                // Archives are extracted into a directory, created if needed
+                let output = match output {
+                    Some(dir) if !is_stdio(dir) => dir,
+                    _ => {
+                        return Err(CompressionError::InvalidArgument(
+                            "Extracting an archive requires an output directory".to_string(),
+                        ))
+                    }
+                };
+                rust_compressor::archive::extract_archive(path_str(input)?, path_str(output)?, &options)?;
+            } else if let Some(dir) = output.filter(|dir| !is_stdio(dir) && dir.is_dir()) {
+                if !is_stdio(input) && !rust_compressor::tarball::is_tar_file(input)? {
+                    return Err(CompressionError::InvalidArgument(format!(
+                        "{} is a directory, but {} is not a compressed tar stream",
+                        dir.display(),
+                        input.display()
+                    )));
+                }
+                rust_compressor::tarball::extract_tar(open_input(input)?, path_str(dir)?, &options)?;
+            } else {
+                // Single stream decompression, processed block by block
+                let reader = open_input(input)?;
+                let writer = create_output(output)?;
+                rust_compressor::decompress_stream(reader, writer)?.flush()?;
+            }
+        }
+        Commands::Test { input } => {
+            let checks = rust_compressor::verify(open_input(input)?, &input.display().to_string())?;
+            let mut out = BufWriter::new(io::stdout().lock());
+            for check in &checks {
+                match &check.error {
+                    None => writeln!(out, "OK    {} ({} bytes)", check.path, check.size)?,
+                    Some(error) => writeln!(out, "FAIL  {}: {}", check.path, error)?,
+                }
+            }
+            out.flush()?;
+
+            let failed = checks.iter().filter(|c| !c.passed()).count();
+            if failed > 0 {
+                return Err(CompressionError::Compression(format!(
+                    "{} of {} entries failed verification",
+                    failed,
+                    checks.len()
+                )));
+            }
+        }
+        Commands::Extract { archive, entries, directory, stdout, overwrite, restore } => {
+            if *stdout {
+                let mut archive = rust_compressor::archive::Archive::open(archive)?;
+                let mut out = BufWriter::new(io::stdout().lock());
+                for name in entries {
+                    io::copy(&mut archive.entry(name)?, &mut out)?;
+                }
+                out.flush()?;
+            } else {
+                let policy = if *overwrite { OverwritePolicy::Overwrite } else { OverwritePolicy::Error };
+                let options = ExtractOptions { overwrite: policy, restore: restore.options() };
+                rust_compressor::archive::extract_entries(
+                    path_str(archive)?,
+                    entries,
+                    path_str(directory)?,
+                    &options,
+                )?;
+            }
+        }
+        Commands::List { archive, json } => {
+            let entries = if !is_stdio(archive) && rust_compressor::archive::is_archive(archive)? {
+                rust_compressor::archive::list_archive(path_str(archive)?)?
+            } else {
+                rust_compressor::tarball::list_tar(open_input(archive)?)?
+            };
+            let mut out = BufWriter::new(io::stdout().lock());
+            if *json {
+                let entries: Vec<_> = entries.iter()
+                    .map(|e| serde_json::json!({
+                        "path": e.path,
+                        "type": e.kind.name(),
+                        "target": e.kind.target(),
+                        "size": e.original_size,
+                        "compressed_size": e.compressed_size,
+                        "ratio": e.ratio(),
+                        "codec": e.algorithm.name(),
+                        "crc32": format!("{:08x}", e.checksum),
+                        "mode": format!("{:o}", e.metadata.mode & 0o7777),
+                        "mtime": e.metadata.mtime,
+                        "mtime_nanos": e.metadata.mtime_nanos,
+                        "uid": e.metadata.uid,
+                        "gid": e.metadata.gid,
+                        "user": e.metadata.user,
+                        "group": e.metadata.group,
+                    }))
+                    .collect();
+                serde_json::to_writer_pretty(&mut out, &entries).map_err(io::Error::other)?;
+                writeln!(out)?;
+            } else {
+                writeln!(
+                    out,
+                    "{:<10}  {:<19}  {:>12} {:>12} {:>7}  {:<8}  PATH",
+                    "MODE", "MODIFIED", "SIZE", "COMPRESSED", "RATIO", "CODEC"
+                )?;
+                for e in &entries {
+                    let (type_char, path) = match &e.kind {
+                        EntryKind::File => ('-', e.path.clone()),
+                        EntryKind::Directory => ('d', e.path.clone()),
+                        EntryKind::Symlink(target) => ('l', format!("{} -> {}", e.path, target)),
+                        EntryKind::HardLink(target) => ('-', format!("{} link to {}", e.path, target)),
+                    };
+                    writeln!(
+                        out,
+                        "{:<10}  {:<19}  {:>12} {:>12} {:>6.1}%  {:<8}  {}",
+                        format!("{}{}", type_char, e.metadata.permissions_string()),
+                        e.metadata.format_mtime(),
+                        e.original_size,
+                        e.compressed_size,
+                        e.ratio() * 100.0,
+                        e.algorithm.name(),
+                        path
+                    )?;
+                }
             }
+            out.flush()?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn verify_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_decompress_output_is_optional() {
+        let cli = Cli::try_parse_from(["rust-compressor", "decompress", "-"]).unwrap();
+        match cli.command {
+            Commands::Decompress { input, output, .. } => {
+                assert!(is_stdio(&input));
+                assert!(output.is_none());
+            }
+            _ => panic!("expected decompress"),
+        }
+    }
+
+    #[test]
+    fn test_level_range_matches_library() {
+        let parse = |level: u32| {
+            Cli::try_parse_from(["rust-compressor", "compress", "in", "out", "--level", &level.to_string()])
+        };
+        assert!(parse(rust_compressor::MIN_LEVEL).is_ok());
+        assert!(parse(rust_compressor::MAX_LEVEL).is_ok());
+        assert!(parse(rust_compressor::MIN_LEVEL - 1).is_err());
+        assert!(parse(rust_compressor::MAX_LEVEL + 1).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_algorithm_flags() {
+        assert!(matches!(explicit_algorithm(None, true, true), Err(CompressionError::InvalidAlgorithm(_))));
+        assert_eq!(explicit_algorithm(None, true, false).unwrap(), Some(Algorithm::Rle));
+        assert_eq!(explicit_algorithm(None, false, false).unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths_are_rejected() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"bad\xff.rz"));
+        assert!(matches!(path_str(path), Err(CompressionError::InvalidArgument(_))));
+        assert_eq!(path_str(Path::new("good.rz")).unwrap(), "good.rz");
+    }
+
+    #[test]
+    fn test_compress_accepts_stdio_paths() {
+        let cli = Cli::try_parse_from(["rust-compressor", "compress", "-", "-", "--algo", "lzh"]).unwrap();
+        match cli.command {
+            Commands::Compress { paths, .. } => assert!(paths.iter().all(|p| is_stdio(p))),
+            _ => panic!("expected compress"),
+        }
+    }
+}
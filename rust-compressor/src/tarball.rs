@@ -0,0 +1,301 @@
+// Tar-based archives: a standard tar stream compressed as a whole with any
+// of the crate's algorithms, e.g. `.tar.lz77` or `.tar.gz`. Once
+// decompressed the stream is an ordinary tar file, and gzip or zlib output
+// can be unpacked by standard tools directly (`tar xzf`).
+//
+// Entries are collected the same way as for the RZAR container, so names,
+// links and metadata match; extraction goes through the same path checks
+// and overwrite policies.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::archive::{self, EntryInfo, EntryKind, ExtractOptions, Extractor, SourceEntry};
+use crate::io::metadata::EntryMetadata;
+use crate::{format, Algorithm};
+
+const BLOCK_LEN: usize = 512;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Checks whether decompressed data starts with a ustar or GNU tar header.
+pub fn is_tar(data: &[u8]) -> bool {
+    data.len() >= BLOCK_LEN && &data[257..262] == b"ustar"
+}
+
+// Checks whether the file at `path` is a compressed stream holding a tar
+// archive. Only the first block is decompressed.
+pub fn is_tar_file(path: &Path) -> io::Result<bool> {
+    let decoder = crate::decoder(io::BufReader::new(File::open(path)?))?;
+    let mut block = Vec::with_capacity(BLOCK_LEN);
+    match decoder.take(BLOCK_LEN as u64).read_to_end(&mut block) {
+        Ok(_) => Ok(is_tar(&block)),
+        Err(e) if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Writes `entries` as a tar stream compressed with `algorithm`.
+pub fn write_tar<W: Write>(entries: &[SourceEntry], mut writer: W, algorithm: Algorithm, level: u32) -> io::Result<W> {
+    crate::check_level(level).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut builder = tar::Builder::new(crate::io::stream::encoder(&mut writer, algorithm, level)?);
+
+    for entry in entries {
+        let mut header = tar_header(&entry.metadata);
+        match &entry.kind {
+            EntryKind::File => {
+                let file = File::open(&entry.path)?;
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(file.metadata()?.len());
+                builder.append_data(&mut header, &entry.name, io::BufReader::new(file))?;
+            }
+            EntryKind::Directory => {
+                header.set_entry_type(tar::EntryType::Directory);
+                builder.append_data(&mut header, &entry.name, io::empty())?;
+            }
+            EntryKind::Symlink(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                builder.append_link(&mut header, &entry.name, target)?;
+            }
+            EntryKind::HardLink(target) => {
+                header.set_entry_type(tar::EntryType::Link);
+                builder.append_link(&mut header, &entry.name, target)?;
+            }
+        }
+    }
+
+    builder.into_inner()?.finish()?;
+    Ok(writer)
+}
+
+fn tar_header(metadata: &EntryMetadata) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_mode(metadata.mode & 0o7777);
+    header.set_mtime(metadata.mtime.max(0) as u64);
+    header.set_uid(metadata.uid as u64);
+    header.set_gid(metadata.gid as u64);
+    // Names too long for the header are left out; the ids still apply.
+    let _ = header.set_username(&metadata.user);
+    let _ = header.set_groupname(&metadata.group);
+    header
+}
+
+pub fn compress_tar(input_paths: &[String], output_path: &str, algorithm: Algorithm, level: u32) -> io::Result<()> {
+    let entries = archive::collect_entries(input_paths)?;
+    let output = io::BufWriter::new(File::create(output_path)?);
+    write_tar(&entries, output, algorithm, level)?.flush()
+}
+
+// Opens a compressed tar stream, returning the algorithm it was compressed
+// with along with the tar reader.
+fn open_tar<'a, R: Read + 'a>(reader: R) -> io::Result<(Algorithm, tar::Archive<Box<dyn Read + 'a>>)> {
+    let reader = crate::peek(reader, format::HEADER_LEN)?;
+    let algorithm = crate::stream_algorithm(reader.get_ref().0.get_ref())
+        .ok_or_else(|| invalid_data("Not a compressed stream".to_string()))?;
+    Ok((algorithm, tar::Archive::new(crate::decoder(reader)?)))
+}
+
+// Tar tools commonly write names with a leading `./` and directories with a
+// trailing slash; neither is part of the entry name.
+fn entry_name(bytes: &[u8]) -> Option<String> {
+    let mut name = std::str::from_utf8(bytes).ok()?.trim_end_matches('/');
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest.trim_start_matches('/');
+    }
+    Some(if name == "." { String::new() } else { name.to_string() })
+}
+
+// Describes a tar member as an archive entry. Tar members aren't compressed
+// individually and carry no CRC32, so the compressed size is the size and
+// the checksum is zero. Members with no archive counterpart, such as
+// devices and FIFOs, and the `.` directory itself give `None`.
+fn entry_info<R: Read>(entry: &tar::Entry<R>, algorithm: Algorithm) -> io::Result<Option<EntryInfo>> {
+    let offset = entry.raw_header_position();
+    let located = |msg: &str| invalid_data(format!("Tar member at offset {}: {}", offset, msg));
+    let path = entry_name(&entry.path_bytes()).ok_or_else(|| located("path is not valid UTF-8"))?;
+    let link = || {
+        let target = entry.link_name_bytes().ok_or_else(|| located("link has no target"))?;
+        String::from_utf8(target.into_owned()).map_err(|_| located("link target is not valid UTF-8"))
+    };
+
+    let header = entry.header();
+    let kind = match header.entry_type() {
+        tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+        tar::EntryType::Directory => EntryKind::Directory,
+        tar::EntryType::Symlink => EntryKind::Symlink(link()?),
+        tar::EntryType::Link => {
+            let target = link()?;
+            EntryKind::HardLink(entry_name(target.as_bytes()).unwrap_or(target))
+        }
+        _ => return Ok(None),
+    };
+    if path.is_empty() {
+        return Ok(None);
+    }
+
+    // Some writers leave numeric fields blank; treat those as zero.
+    let name = |name: Result<Option<&str>, _>| name.ok().flatten().unwrap_or_default().to_string();
+    let metadata = EntryMetadata {
+        mode: header.mode().unwrap_or(0o644),
+        mtime: header.mtime().unwrap_or(0) as i64,
+        mtime_nanos: 0,
+        uid: header.uid().unwrap_or(0) as u32,
+        gid: header.gid().unwrap_or(0) as u32,
+        user: name(header.username()),
+        group: name(header.groupname()),
+    };
+    let size = entry.size();
+    Ok(Some(EntryInfo {
+        path,
+        kind,
+        algorithm,
+        original_size: size,
+        compressed_size: size,
+        checksum: 0,
+        offset,
+        metadata,
+    }))
+}
+
+// Lists the members of a compressed tar stream. The whole stream is
+// decompressed, since tar has no index.
+pub fn list_tar<R: Read>(reader: R) -> io::Result<Vec<EntryInfo>> {
+    let (algorithm, mut tar) = open_tar(reader)?;
+    let mut entries = Vec::new();
+    for entry in tar.entries()? {
+        if let Some(info) = entry_info(&entry?, algorithm)? {
+            entries.push(info);
+        }
+    }
+    Ok(entries)
+}
+
+pub fn extract_tar<R: Read>(reader: R, output_dir: &str, options: &ExtractOptions) -> io::Result<()> {
+    let (algorithm, mut tar) = open_tar(reader)?;
+    let mut extractor = Extractor::new(output_dir, options, false)?;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let Some(info) = entry_info(&entry, algorithm)? else {
+            continue;
+        };
+        if let Some(path) = extractor.start(&info)? {
+            extractor.write_file(&info, &path, |out| io::copy(&mut entry, out).map(drop))?;
+        }
+    }
+    extractor.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn path_str(path: &Path) -> String {
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_entry_names() {
+        assert_eq!(entry_name(b"./a/b/").unwrap(), "a/b");
+        assert_eq!(entry_name(b"./").unwrap(), "");
+        assert_eq!(entry_name(b"a/./b").unwrap(), "a/./b");
+        assert_eq!(entry_name(b"/etc/passwd").unwrap(), "/etc/passwd");
+        assert!(entry_name(b"\xff").is_none());
+    }
+
+    #[test]
+    fn test_tar_round_trip_with_each_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("a.txt"), b"tar me ".repeat(300)).unwrap();
+        fs::write(root.join("b.bin"), [0u8, 1, 2, 3]).unwrap();
+        let prefix = archive::archive_path(&root).unwrap();
+
+        for algorithm in [Algorithm::Lz77, Algorithm::Lzh, Algorithm::Deflate, Algorithm::Gzip, Algorithm::Zlib] {
+            let output = dir.path().join(format!("src.tar.{}", algorithm.name()));
+            compress_tar(&[path_str(&root)], &path_str(&output), algorithm, 6).unwrap();
+            assert!(is_tar_file(&output).unwrap());
+            assert!(!archive::is_archive(&output).unwrap());
+
+            let entries = list_tar(File::open(&output).unwrap()).unwrap();
+            let names: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+            let expected = [prefix.clone(), format!("{}/a.txt", prefix), format!("{}/b.bin", prefix), format!("{}/empty", prefix)];
+            assert_eq!(names, expected);
+            assert!(entries.iter().all(|e| e.algorithm == algorithm));
+
+            let out = dir.path().join(format!("out-{}", algorithm.name()));
+            extract_tar(File::open(&output).unwrap(), &path_str(&out), &ExtractOptions::default()).unwrap();
+            assert_eq!(fs::read(out.join(&prefix).join("a.txt")).unwrap(), b"tar me ".repeat(300));
+            assert_eq!(fs::read(out.join(&prefix).join("b.bin")).unwrap(), [0, 1, 2, 3]);
+            assert!(out.join(&prefix).join("empty").is_dir());
+        }
+    }
+
+    #[test]
+    fn test_gzip_output_is_a_plain_tar_gz() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, b"read me with tar").unwrap();
+        let output = dir.path().join("notes.tar.gz");
+        compress_tar(&[path_str(&file)], &path_str(&output), Algorithm::Gzip, 6).unwrap();
+
+        // Readable with nothing but flate2 and tar.
+        let decoder = flate2::read::GzDecoder::new(File::open(&output).unwrap());
+        let mut tar = tar::Archive::new(decoder);
+        let mut entry = tar.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap(), Path::new(&archive::archive_path(&file).unwrap()));
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "read me with tar");
+    }
+
+    #[test]
+    fn test_foreign_tar_with_dot_prefix() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        builder.append_data(&mut header, "./", io::empty()).unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_size(5);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "./dir/file.txt", &b"hello"[..]).unwrap();
+        let compressed = crate::compress_stream(&builder.into_inner().unwrap()[..], Vec::new(), Algorithm::Lz77, 6).unwrap();
+
+        let entries = list_tar(&compressed[..]).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "dir/file.txt");
+
+        let dir = tempfile::tempdir().unwrap();
+        extract_tar(&compressed[..], &path_str(dir.path()), &ExtractOptions::default()).unwrap();
+        assert_eq!(fs::read(dir.path().join("dir/file.txt")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_tar_escapes_rejected() {
+        // The tar crate refuses to write `..` names, so patch one in.
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_size(5);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "xx/escaped.txt", &b"pwned"[..]).unwrap();
+        let mut raw = builder.into_inner().unwrap();
+        raw[..2].copy_from_slice(b"..");
+        let mut header = tar::Header::from_byte_slice(&raw[..BLOCK_LEN]).clone();
+        header.set_cksum();
+        raw[..BLOCK_LEN].copy_from_slice(header.as_bytes());
+        let compressed = crate::compress_stream(&raw[..], Vec::new(), Algorithm::Lz77, 6).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let err = extract_tar(&compressed[..], &path_str(&out), &ExtractOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+        assert!(!dir.path().join("escaped.txt").exists());
+    }
+}